@@ -33,8 +33,8 @@ impl Aabb {
                 .min((self.max.a[a] - r.orig.a[a]) / r.dir.a[a]);
             let t1 = ((self.min.a[a] - r.orig.a[a]) / r.dir.a[a])
                 .max((self.max.a[a] - r.orig.a[a]) / r.dir.a[a]);
-            min = min.min(t0);
-            max = max.max(t1);
+            min = min.max(t0);
+            max = max.min(t1);
             if max <= min {
                 return false;
             }
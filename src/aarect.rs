@@ -1,12 +1,29 @@
 use crate::aabb::Aabb;
-use crate::hittable::{HitRecord, Hittable};
+use crate::color::luminance;
+use crate::hittable::{new_object_id, HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::{Point3, Ray};
 use crate::utils::random_range;
 use crate::vec3::Vec3;
 
+use std::f64::consts::PI;
 use std::sync::Arc;
 
+fn rect_power(mat: &Arc<dyn Material>, area: f64) -> f64 {
+    PI * area * luminance(mat.average_emitted())
+}
+
+fn rect_pdf_value(rect: &dyn Hittable, area: f64, origin: &Point3, v: &Vec3, time: f64) -> f64 {
+    if let Some(rec) = rect.hit(&Ray::new(*origin, *v, time), 0.001, f64::INFINITY) {
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = v.dot(rec.normal).abs() / v.length();
+
+        distance_squared / (cosine * area)
+    } else {
+        0.0
+    }
+}
+
 pub struct XYRect {
     mat: Arc<dyn Material>,
     x0: f64,
@@ -14,6 +31,7 @@ pub struct XYRect {
     y0: f64,
     y1: f64,
     k: f64,
+    id: u32,
 }
 
 impl Hittable for XYRect {
@@ -31,15 +49,26 @@ impl Hittable for XYRect {
         let v = (y - self.y0) / (self.y1 - self.y0);
         let outward_normal = Vec3::new(0., 0., 1.);
         let mut rec = HitRecord::new(r.at(t), t, u, v, self.mat.clone());
+        rec.object_id = self.id;
         rec.set_face_normal(r, &outward_normal);
         Some(rec)
     }
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(Aabb::new(
             Point3::new(self.x0, self.y0, self.k - 0.0001),
-            Point3::new(self.x0, self.y0, self.k + 0.0001),
+            Point3::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+    fn pdf_value(&self, origin: &Point3, v: &Vec3, time: f64) -> f64 {
+        rect_pdf_value(self, (self.x1 - self.x0) * (self.y1 - self.y0), origin, v, time)
+    }
+    fn random(&self, origin: &Point3, _time: f64) -> Vec3 {
+        let random_point = Point3::new(random_range(self.x0, self.x1), random_range(self.y0, self.y1), self.k);
+        random_point - *origin
+    }
+    fn power(&self) -> f64 {
+        rect_power(&self.mat, (self.x1 - self.x0) * (self.y1 - self.y0))
+    }
 }
 
 impl XYRect {
@@ -51,6 +80,7 @@ impl XYRect {
             y1: y1,
             k: k,
             mat: mat,
+            id: new_object_id(),
         }
     }
 }
@@ -62,6 +92,7 @@ pub struct XZRect {
     z0: f64,
     z1: f64,
     k: f64,
+    id: u32,
 }
 
 impl Hittable for XZRect {
@@ -79,30 +110,26 @@ impl Hittable for XZRect {
         let v = (z - self.z0) / (self.z1 - self.z0);
         let outward_normal = Vec3::new(0., 1., 0.);
         let mut rec = HitRecord::new(r.at(t), t, u, v, self.mat.clone());
+        rec.object_id = self.id;
         rec.set_face_normal(r, &outward_normal);
         Some(rec)
     }
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(Aabb::new(
             Point3::new(self.x0, self.k - 0.0001, self.z0),
-            Point3::new(self.x0, self.k + 0.0001, self.z1),
+            Point3::new(self.x1, self.k + 0.0001, self.z1),
         ))
     }
-    fn pdf_value(&self, origin: &Point3, v: &Vec3) -> f64 {
-        if let Some(rec) = self.hit(&Ray::new(*origin, *v, 0.0), 0.001, f64::INFINITY) {
-            let area = (self.x1 - self.x0) * (self.z1 - self.z0);
-            let distance_squared = rec.t * rec.t * v.length_squared();
-            let cosine = v.dot(rec.normal).abs() / v.length();
-
-            distance_squared / (cosine * area)
-        } else {
-            0.0
-        }
+    fn pdf_value(&self, origin: &Point3, v: &Vec3, time: f64) -> f64 {
+        rect_pdf_value(self, (self.x1 - self.x0) * (self.z1 - self.z0), origin, v, time)
     }
-    fn random(&self, origin: &Point3) -> Vec3 {
+    fn random(&self, origin: &Point3, _time: f64) -> Vec3 {
         let random_point = Point3::new(random_range(self.x0, self.x1), self.k, random_range(self.z0, self.z1));
         random_point - *origin
     }
+    fn power(&self) -> f64 {
+        rect_power(&self.mat, (self.x1 - self.x0) * (self.z1 - self.z0))
+    }
 }
 
 impl XZRect {
@@ -114,6 +141,7 @@ impl XZRect {
             z1: z1,
             k: k,
             mat: mat,
+            id: new_object_id(),
         }
     }
 }
@@ -125,6 +153,7 @@ pub struct YZRect {
     z0: f64,
     z1: f64,
     k: f64,
+    id: u32,
 }
 
 impl Hittable for YZRect {
@@ -142,15 +171,26 @@ impl Hittable for YZRect {
         let v = (z - self.z0) / (self.z1 - self.z0);
         let outward_normal = Vec3::new(1., 0., 0.);
         let mut rec = HitRecord::new(r.at(t), t, u, v, self.mat.clone());
+        rec.object_id = self.id;
         rec.set_face_normal(r, &outward_normal);
         Some(rec)
     }
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(Aabb::new(
             Point3::new(self.k - 0.0001, self.y0, self.z0),
-            Point3::new(self.k + 0.0001, self.y0, self.z0),
+            Point3::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+    fn pdf_value(&self, origin: &Point3, v: &Vec3, time: f64) -> f64 {
+        rect_pdf_value(self, (self.y1 - self.y0) * (self.z1 - self.z0), origin, v, time)
+    }
+    fn random(&self, origin: &Point3, _time: f64) -> Vec3 {
+        let random_point = Point3::new(self.k, random_range(self.y0, self.y1), random_range(self.z0, self.z1));
+        random_point - *origin
+    }
+    fn power(&self) -> f64 {
+        rect_power(&self.mat, (self.y1 - self.y0) * (self.z1 - self.z0))
+    }
 }
 
 impl YZRect {
@@ -162,6 +202,7 @@ impl YZRect {
             z1: z1,
             k: k,
             mat: mat,
+            id: new_object_id(),
         }
     }
 }
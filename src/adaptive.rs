@@ -0,0 +1,105 @@
+use crate::color::*;
+
+// Keeps sampling a pixel in batches of `min_samples` until the standard
+// error of its mean luminance falls below `target_error` relative to the
+// mean, or the sample budget runs out.
+#[derive(Clone)]
+pub struct AdaptiveSampling {
+    pub min_samples: usize,
+    target_error: f64,
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples: usize, target_error: f64) -> AdaptiveSampling {
+        AdaptiveSampling {
+            min_samples: min_samples.max(2),
+            target_error,
+        }
+    }
+
+    pub fn converged(&self, stats: &PixelStats) -> bool {
+        stats.count >= self.min_samples && stats.count % self.min_samples == 0 && stats.relative_error() < self.target_error
+    }
+}
+
+// Running mean and variance of sample luminance (Welford).
+#[derive(Clone, Copy)]
+pub struct PixelStats {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl PixelStats {
+    pub fn new() -> PixelStats {
+        PixelStats {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, c: Color) {
+        let l = luminance(c);
+        self.count += 1;
+        let delta = l - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (l - self.mean);
+    }
+
+    pub fn from_parts(count: usize, mean: f64, m2: f64) -> PixelStats {
+        PixelStats { count, mean, m2 }
+    }
+
+    pub fn parts(&self) -> (usize, f64, f64) {
+        (self.count, self.mean, self.m2)
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // Folds in statistics of a disjoint set of samples (Chan et al.).
+    pub fn merge(&mut self, other: &PixelStats) {
+        if other.count == 0 {
+            return;
+        }
+        let n = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.m2 += other.m2 + delta * delta * (self.count * other.count) as f64 / n as f64;
+        self.mean += delta * other.count as f64 / n as f64;
+        self.count = n;
+    }
+
+    // Estimated variance of the pixel's mean luminance.
+    pub fn variance_of_mean(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        self.m2 / ((self.count - 1) * self.count) as f64
+    }
+
+    pub fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        // the offset lets black pixels converge instead of dividing by zero
+        self.variance_of_mean().sqrt() / (self.mean.abs() + 1e-3)
+    }
+}
+
+// Blue through green and yellow to red, for t in [0, 1].
+pub fn heatmap_color(t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    let stops = [
+        Color::new(0.0, 0.0, 0.5),
+        Color::new(0.0, 0.4, 1.0),
+        Color::new(0.0, 0.9, 0.3),
+        Color::new(1.0, 0.9, 0.0),
+        Color::new(0.9, 0.0, 0.0),
+    ];
+    let x = t * (stops.len() - 1) as f64;
+    let i = (x as usize).min(stops.len() - 2);
+    let f = x - i as f64;
+    (1.0 - f) * stops[i] + f * stops[i + 1]
+}
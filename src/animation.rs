@@ -0,0 +1,374 @@
+use crate::aabb::Aabb;
+use crate::camera::Camera;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::*;
+use crate::utils::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// An affine transform, as the rows of a 3 x 4 matrix, kept with its
+// inverse so that inverse rays and normals cost no more than points.
+#[derive(Clone, Copy)]
+pub struct Transform {
+    m: [[f64; 4]; 3],
+    inv: [[f64; 4]; 3],
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        let m = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]];
+        Transform { m, inv: m }
+    }
+
+    // Scales, then rotates about x, y and z in turn by `rotation` degrees,
+    // then moves to `position`.
+    pub fn new(position: Vec3, rotation: Vec3, scale: Vec3) -> Transform {
+        let (sx, cx) = degrees_to_radians(rotation.x()).sin_cos();
+        let (sy, cy) = degrees_to_radians(rotation.y()).sin_cos();
+        let (sz, cz) = degrees_to_radians(rotation.z()).sin_cos();
+        // Rz * Ry * Rx
+        let r = [
+            [cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx],
+            [sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx],
+            [-sy, cy * sx, cy * cx],
+        ];
+        let mut m = [[0.0; 4]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                m[i][j] = r[i][j] * scale[j];
+            }
+            m[i][3] = position[i];
+        }
+        Transform { m, inv: invert(&m) }
+    }
+
+    pub fn point(&self, p: Vec3) -> Point3 {
+        self.vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        linear(&self.m, v)
+    }
+
+    // Normals go through the inverse transpose.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.inv;
+        Vec3::new(
+            m[0][0] * n[0] + m[1][0] * n[1] + m[2][0] * n[2],
+            m[0][1] * n[0] + m[1][1] * n[1] + m[2][1] * n[2],
+            m[0][2] * n[0] + m[1][2] * n[1] + m[2][2] * n[2],
+        )
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform { m: self.inv, inv: self.m }
+    }
+
+    // Determinant of the linear part, the change in volume.
+    pub fn determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * cofactor(m, 0, 0) + m[0][1] * cofactor(m, 0, 1) + m[0][2] * cofactor(m, 0, 2)
+    }
+
+    // Bounds of the transformed corners of `b`.
+    pub fn bounding_box(&self, b: &Aabb) -> Aabb {
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for corner in corners(b) {
+            let p = self.point(corner);
+            for c in 0..3 {
+                min[c] = min[c].min(p[c]);
+                max[c] = max[c].max(p[c]);
+            }
+        }
+        Aabb::new(min, max)
+    }
+}
+
+fn linear(m: &[[f64; 4]; 3], v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    )
+}
+
+fn cofactor(m: &[[f64; 4]; 3], i: usize, j: usize) -> f64 {
+    let (i0, i1) = ((i + 1) % 3, (i + 2) % 3);
+    let (j0, j1) = ((j + 1) % 3, (j + 2) % 3);
+    m[i0][j0] * m[i1][j1] - m[i0][j1] * m[i1][j0]
+}
+
+// Cofactors of the linear part over its determinant, and the offset that
+// undoes the translation.
+fn invert(m: &[[f64; 4]; 3]) -> [[f64; 4]; 3] {
+    let det = m[0][0] * cofactor(m, 0, 0) + m[0][1] * cofactor(m, 0, 1) + m[0][2] * cofactor(m, 0, 2);
+    let mut inv = [[0.0; 4]; 3];
+    for (i, row) in inv.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().take(3).enumerate() {
+            *x = cofactor(m, j, i) / det;
+        }
+    }
+    let offset = -linear(&inv, Vec3::new(m[0][3], m[1][3], m[2][3]));
+    for (i, row) in inv.iter_mut().enumerate() {
+        row[3] = offset[i];
+    }
+    inv
+}
+
+fn corners(b: &Aabb) -> Vec<Point3> {
+    (0..8)
+        .map(|i| {
+            Point3::new(
+                if i & 1 == 0 { b.min.x() } else { b.max.x() },
+                if i & 2 == 0 { b.min.y() } else { b.max.y() },
+                if i & 4 == 0 { b.min.z() } else { b.max.z() },
+            )
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+pub struct Keyframe {
+    pub time: f64,
+    pub position: Vec3,
+    // degrees about x, y and z, applied in that order
+    pub rotation: Vec3,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f64, position: Vec3, rotation: Vec3, scale: Vec3) -> Keyframe {
+        Keyframe {
+            time,
+            position,
+            rotation,
+            scale,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Interpolation {
+    Linear,
+    // cubic Bézier segments with Catmull-Rom handles, smooth through every
+    // key however the keys are spaced in time
+    Bezier,
+}
+
+// Keyframes sorted by time; before the first and after the last key the
+// transform holds still.
+pub struct Animation {
+    keys: Vec<Keyframe>,
+    interpolation: Interpolation,
+}
+
+impl Animation {
+    pub fn new(mut keys: Vec<Keyframe>, interpolation: Interpolation) -> Animation {
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        Animation { keys, interpolation }
+    }
+
+    // No keys, so always the identity.
+    pub fn still() -> Animation {
+        Animation::new(Vec::new(), Interpolation::Linear)
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let n = self.keys.len();
+        if n == 0 {
+            return Transform::identity();
+        }
+        let i = self.keys.partition_point(|k| k.time <= time);
+        if i == 0 || i == n {
+            let k = &self.keys[i.min(n - 1)];
+            return Transform::new(k.position, k.rotation, k.scale);
+        }
+        let (k0, k1) = (&self.keys[i - 1], &self.keys[i]);
+        let u = (time - k0.time) / (k1.time - k0.time);
+        let key = |j: usize| &self.keys[j.clamp(1, n) - 1];
+        let value = |f: fn(&Keyframe) -> Vec3| match self.interpolation {
+            Interpolation::Linear => (1.0 - u) * f(k0) + u * f(k1),
+            Interpolation::Bezier => {
+                let (p0, p3) = (f(k0), f(k1));
+                // the tangent at a key is the velocity between its neighbours,
+                // taken over this segment's length in time
+                let (before, after) = (key(i - 1), key(i + 2));
+                let dt = k1.time - k0.time;
+                let p1 = p0 + dt / (3.0 * (k1.time - before.time)) * (p3 - f(before));
+                let p2 = p3 - dt / (3.0 * (after.time - k0.time)) * (f(after) - p0);
+                let v = 1.0 - u;
+                v * v * v * p0 + 3.0 * v * v * u * p1 + 3.0 * v * u * u * p2 + u * u * u * p3
+            }
+        };
+        Transform::new(value(|k| k.position), value(|k| k.rotation), value(|k| k.scale))
+    }
+
+    // Times at which to sample the animation to bound it over [time0, time1].
+    fn sample_times(&self, time0: f64, time1: f64) -> Vec<f64> {
+        const STEPS: usize = 32;
+        let mut times: Vec<f64> = (0..=STEPS).map(|i| time0 + (time1 - time0) * i as f64 / STEPS as f64).collect();
+        times.extend(self.keys.iter().map(|k| k.time).filter(|t| *t > time0 && *t < time1));
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        times
+    }
+}
+
+// Moves a hittable along a keyframed animation, at the time of each ray.
+pub struct Animated {
+    h: Arc<dyn Hittable>,
+    animation: Arc<Animation>,
+}
+
+impl Animated {
+    pub fn new(h: Arc<dyn Hittable>, animation: Arc<Animation>) -> Animated {
+        Animated { h, animation }
+    }
+}
+
+impl Hittable for Animated {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let m = self.animation.at(r.time);
+        let inv = m.inverse();
+        let mut local_r = Ray::new(inv.point(r.orig), inv.vector(r.dir), r.time);
+        local_r.wavelength = r.wavelength;
+        // affine maps keep the ray parameter, so t carries over
+        let mut rec = self.h.hit(&local_r, t_min, t_max)?;
+        rec.p = m.point(rec.p);
+        rec.normal = m.normal(rec.normal).unit_vector();
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let b = self.h.bounding_box(time0, time1)?;
+        let times = self.animation.sample_times(time0, time1);
+        let transforms: Vec<Transform> = times.iter().map(|t| self.animation.at(*t)).collect();
+        let mut out = transforms[0].bounding_box(&b);
+        // corners sweep curves between the samples; pad by half the longest
+        // step to cover the bulge
+        let mut pad: f64 = 0.0;
+        for pair in transforms.windows(2) {
+            out = Aabb::surrounding_box(out, pair[1].bounding_box(&b));
+            for corner in corners(&b) {
+                pad = pad.max((pair[1].point(corner) - pair[0].point(corner)).length() / 2.0);
+            }
+        }
+        let pad = Vec3::new(pad, pad, pad);
+        Some(Aabb::new(out.min - pad, out.max + pad))
+    }
+
+    // The inner pdf is per solid angle seen from the untransformed origin.
+    // The inverse maps a unit direction d to inv(d) / |inv(d)|, stretching
+    // solid angle by |det inv| / |inv(d)|^3, which is 1 unless the transform
+    // scales unevenly.
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        let inv = self.animation.at(time).inverse();
+        let local = inv.vector(v.unit_vector());
+        self.h.pdf_value(&inv.point(*o), &local, time) * inv.determinant().abs() / local.length().powi(3)
+    }
+
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        let m = self.animation.at(time);
+        m.vector(self.h.random(&m.inverse().point(*o), time))
+    }
+
+    fn power(&self) -> f64 {
+        self.h.power()
+    }
+}
+
+// Carries a camera rig along a keyframed animation. The rig's shutter, open
+// from time 0 to 1, is mapped onto [time0, time1] of the scene's clock, so
+// each frame of a sequence blurs over its own interval.
+pub struct AnimatedCamera {
+    camera: Arc<dyn Camera>,
+    animation: Arc<Animation>,
+    time0: f64,
+    time1: f64,
+}
+
+impl AnimatedCamera {
+    pub fn new(camera: Arc<dyn Camera>, animation: Arc<Animation>, time0: f64, time1: f64) -> AnimatedCamera {
+        AnimatedCamera {
+            camera,
+            animation,
+            time0,
+            time1,
+        }
+    }
+}
+
+impl Camera for AnimatedCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let r = self.camera.get_ray(s, t)?;
+        let time = self.time0 + r.time * (self.time1 - self.time0);
+        let m = self.animation.at(time);
+        Some(Ray::new(m.point(r.orig), m.vector(r.dir), time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::DiffuseLight;
+    use crate::sphere::Sphere;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(
+            (a - b).length() < 1e-9,
+            "{:?} != {:?}",
+            (a.x(), a.y(), a.z()),
+            (b.x(), b.y(), b.z())
+        );
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let t = Transform::new(Vec3::new(1.0, -2.0, 3.0), Vec3::new(30.0, 45.0, -60.0), Vec3::new(2.0, 0.5, 3.0));
+        let inv = t.inverse();
+        for p in [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 2.0, 3.0), Vec3::new(-4.0, 0.5, 7.0)] {
+            assert_close(inv.point(t.point(p)), p);
+            assert_close(inv.vector(t.vector(p)), p);
+        }
+    }
+
+    #[test]
+    fn unevenly_scaled_light_pdf_integrates_to_one() {
+        seed_random(3);
+        let light = Arc::new(DiffuseLight::new_color(Color::new(1.0, 1.0, 1.0)));
+        let ball = Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, light));
+        let stretch = Keyframe::new(0.0, Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 30.0, 0.0), Vec3::new(3.0, 1.0, 0.5));
+        let animated = Animated::new(ball, Arc::new(Animation::new(vec![stretch], Interpolation::Linear)));
+        // uniform directions from the origin, each standing for 4 pi / N sr
+        const N: usize = 200000;
+        let o = Point3::new(0.0, 0.0, 0.0);
+        let total: f64 = (0..N).map(|_| animated.pdf_value(&o, &Vec3::random_unit_vector(), 0.0)).sum();
+        let integral = total * 4.0 * std::f64::consts::PI / N as f64;
+        assert!((integral - 1.0).abs() < 0.05, "pdf integrates to {}", integral);
+    }
+
+    #[test]
+    fn bezier_velocity_is_continuous_across_unevenly_spaced_keys() {
+        let key = |time: f64, x: f64| Keyframe::new(time, Vec3::new(x, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        let animation = Animation::new(
+            vec![key(0.0, 0.0), key(0.2, 1.0), key(1.2, 2.0), key(1.4, 5.0)],
+            Interpolation::Bezier,
+        );
+        let position = |time: f64| animation.at(time).point(Vec3::new(0.0, 0.0, 0.0));
+        assert_close(position(0.2), Vec3::new(1.0, 0.0, 0.0));
+        assert_close(position(1.2), Vec3::new(2.0, 0.0, 0.0));
+        let h = 1e-6;
+        for t in [0.2, 1.2] {
+            let before = (position(t) - position(t - h)) / h;
+            let after = (position(t + h) - position(t)) / h;
+            assert!(
+                (before - after).length() < 1e-3,
+                "velocity jumps at {}: {} vs {}",
+                t,
+                before.x(),
+                after.x()
+            );
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use crate::color::*;
+use crate::hittable::HitRecord;
+use crate::lpe::{Event, LightPathExpression};
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Auxiliary render passes written alongside the beauty image.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Position,
+    Depth,
+    Uv,
+    ObjectId,
+    MaterialId,
+    Direct,
+    Indirect,
+    Emission,
+    SampleCount,
+    // light reaching the camera from one light group
+    LightGroup(u32),
+    // light along paths matching one of the light path expressions
+    Lpe(usize),
+}
+
+impl Aov {
+    pub fn name(&self) -> String {
+        match self {
+            Aov::Albedo => "albedo".to_string(),
+            Aov::Normal => "normal".to_string(),
+            Aov::Position => "position".to_string(),
+            Aov::Depth => "depth".to_string(),
+            Aov::Uv => "uv".to_string(),
+            Aov::ObjectId => "object_id".to_string(),
+            Aov::MaterialId => "material_id".to_string(),
+            Aov::Direct => "direct".to_string(),
+            Aov::Indirect => "indirect".to_string(),
+            Aov::Emission => "emission".to_string(),
+            Aov::SampleCount => "samples".to_string(),
+            Aov::LightGroup(g) => format!("light_group{}", g),
+            Aov::Lpe(i) => format!("lpe{}", i),
+        }
+    }
+
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo | Aov::Direct | Aov::Indirect | Aov::Emission | Aov::LightGroup(_) | Aov::Lpe(_) => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::Uv => &["U", "V"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::SampleCount => &["count"],
+        }
+    }
+
+    // Ids are taken from a pixel's first sample; averaging them would mix ids.
+    pub fn is_id(&self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+// What one camera sample saw at its first hit, plus its radiance split by
+// the number of bounces it took to reach a light, by light group, and by
+// light path expression.
+pub struct AovSample {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub position: Vec3,
+    pub depth: f64,
+    pub uv: (f64, f64),
+    pub object_id: u32,
+    pub material_id: u32,
+    pub direct: Color,
+    pub indirect: Color,
+    pub emission: Color,
+    // indexed by group; group 0 holds everything not in a group
+    pub light_groups: Vec<Color>,
+    pub lpe_values: Vec<Color>,
+    pub lpes: Arc<Vec<LightPathExpression>>,
+    // per expression, its state after the events from the camera up to the
+    // vertex before this hit
+    pub lpe_states: Vec<u64>,
+}
+
+impl AovSample {
+    pub fn new(light_groups: usize, lpes: Arc<Vec<LightPathExpression>>) -> AovSample {
+        let black = Color::new(0.0, 0.0, 0.0);
+        AovSample {
+            albedo: black,
+            normal: black,
+            position: black,
+            depth: 0.0,
+            uv: (0.0, 0.0),
+            object_id: 0,
+            material_id: 0,
+            direct: black,
+            indirect: black,
+            emission: black,
+            light_groups: vec![black; light_groups],
+            lpe_values: vec![black; lpes.len()],
+            lpe_states: lpes.iter().map(|l| l.step(l.start(), Event::Camera)).collect(),
+            lpes,
+        }
+    }
+
+    // An empty sample for the next vertex of the path, reached by `event`.
+    pub fn next(&self, event: Event) -> AovSample {
+        let mut next = AovSample::new(self.light_groups.len(), self.lpes.clone());
+        for (s, (state, lpe)) in next.lpe_states.iter_mut().zip(self.lpe_states.iter().zip(self.lpes.iter())) {
+            *s = lpe.step(*state, event);
+        }
+        next
+    }
+
+    // Adds light that leaves a light after the given events past this
+    // sample's path to the matching expressions.
+    pub fn add_to_lpes(&mut self, events: &[Event], c: Color) {
+        for ((value, state), lpe) in self.lpe_values.iter_mut().zip(&self.lpe_states).zip(self.lpes.iter()) {
+            let state = events.iter().fold(*state, |s, e| lpe.step(s, *e));
+            if lpe.accepts(lpe.step(state, Event::Light)) {
+                *value += c;
+            }
+        }
+    }
+
+    // Adds light from a group, clamping unknown groups to group 0.
+    pub fn add_to_group(&mut self, group: u32, c: Color) {
+        let g = if (group as usize) < self.light_groups.len() {
+            group as usize
+        } else {
+            0
+        };
+        self.light_groups[g] += c;
+    }
+
+    pub fn record_hit(&mut self, r: &Ray, rec: &HitRecord) {
+        self.normal = rec.normal;
+        self.position = rec.p;
+        self.depth = rec.t * r.dir.length();
+        self.uv = (rec.u, rec.v);
+        self.object_id = rec.object_id;
+        self.material_id = rec.mat.id();
+    }
+
+    // Values of a pass in channel order; sample counts come from the film.
+    pub fn values(&self, aov: Aov) -> Vec<f64> {
+        match aov {
+            Aov::Albedo => self.albedo.a.to_vec(),
+            Aov::Normal => self.normal.a.to_vec(),
+            Aov::Position => self.position.a.to_vec(),
+            Aov::Depth => vec![self.depth],
+            Aov::Uv => vec![self.uv.0, self.uv.1],
+            Aov::ObjectId => vec![self.object_id as f64],
+            Aov::MaterialId => vec![self.material_id as f64],
+            Aov::Direct => self.direct.a.to_vec(),
+            Aov::Indirect => self.indirect.a.to_vec(),
+            Aov::Emission => self.emission.a.to_vec(),
+            Aov::SampleCount => vec![0.0],
+            Aov::LightGroup(g) => self.light_groups.get(g as usize).map_or(vec![0.0; 3], |c| c.a.to_vec()),
+            Aov::Lpe(i) => self.lpe_values.get(i).map_or(vec![0.0; 3], |c| c.a.to_vec()),
+        }
+    }
+}
+
+// Per-pixel storage for one pass, shared by the film and its tiles.
+pub struct AovBuffer {
+    pub aov: Aov,
+    values: Vec<f64>,
+    counts: Vec<u32>,
+}
+
+impl AovBuffer {
+    pub fn new(aov: Aov, pixels: usize) -> AovBuffer {
+        AovBuffer {
+            aov,
+            values: vec![0.0; pixels * aov.channels().len()],
+            counts: vec![0; pixels],
+        }
+    }
+
+    pub fn add(&mut self, i: usize, sample: &AovSample) {
+        self.add_values(i, &sample.values(self.aov), 1);
+    }
+
+    fn add_values(&mut self, i: usize, values: &[f64], count: u32) {
+        let n = values.len();
+        if self.aov.is_id() {
+            if self.counts[i] == 0 {
+                self.values[i * n..(i + 1) * n].copy_from_slice(values);
+            }
+        } else {
+            for (v, x) in self.values[i * n..(i + 1) * n].iter_mut().zip(values) {
+                *v += x;
+            }
+        }
+        self.counts[i] += count;
+    }
+
+    pub fn merge(&mut self, i: usize, other: &AovBuffer, j: usize) {
+        let n = self.aov.channels().len();
+        if other.counts[j] > 0 {
+            self.add_values(i, &other.values[j * n..(j + 1) * n], other.counts[j]);
+        }
+    }
+
+    pub fn value(&self, i: usize) -> Vec<f64> {
+        let n = self.aov.channels().len();
+        let v = &self.values[i * n..(i + 1) * n];
+        if self.aov.is_id() || self.counts[i] == 0 {
+            v.to_vec()
+        } else {
+            v.iter().map(|x| x / self.counts[i] as f64).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, Metal};
+
+    #[test]
+    fn material_ids_follow_creation_order() {
+        let a = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let b = Metal::new(Color::new(0.5, 0.5, 0.5), 0.0);
+        assert!(a.id() > 0);
+        assert_eq!(b.id(), a.id() + 1);
+    }
+
+    #[test]
+    fn id_passes_keep_the_first_sample_and_others_average() {
+        let mut ids = AovBuffer::new(Aov::MaterialId, 1);
+        let mut depth = AovBuffer::new(Aov::Depth, 1);
+        for (id, d) in [(3, 1.0), (5, 3.0)] {
+            let mut sample = AovSample::new(1, Arc::new(Vec::new()));
+            sample.material_id = id;
+            sample.depth = d;
+            ids.add(0, &sample);
+            depth.add(0, &sample);
+        }
+        assert_eq!(ids.value(0), [3.0]);
+        assert_eq!(depth.value(0), [2.0]);
+        assert_eq!(Aov::SampleCount.channels(), ["count"]);
+    }
+}
@@ -2,13 +2,22 @@ use crate::aabb::*;
 use crate::hittable::*;
 use crate::hittable_list::*;
 use crate::ray::*;
-use crate::utils::*;
 use std::sync::Arc;
 
+// Number of equal parts of the shutter interval a node keeps bounds for.
+const TIME_SEGMENTS: usize = 8;
+
+// Each node bounds its children separately over every part of the shutter
+// interval, so a fast moving object only widens the boxes of the rays timed
+// near it rather than the whole hierarchy.
 pub struct BVHNode {
     left: Arc<dyn Hittable>,
     right: Arc<dyn Hittable>,
     bbox: Aabb,
+    // bounds per segment; empty when the children hold still
+    segments: Vec<Aabb>,
+    time0: f64,
+    time1: f64,
 }
 
 impl BVHNode {
@@ -18,14 +27,39 @@ impl BVHNode {
         time0: f64,
         time1: f64,
     ) -> BVHNode {
+        let mut segments: Vec<Aabb> = (0..TIME_SEGMENTS)
+            .map(|i| {
+                let t0 = time0 + (time1 - time0) * i as f64 / TIME_SEGMENTS as f64;
+                let t1 = time0 + (time1 - time0) * (i + 1) as f64 / TIME_SEGMENTS as f64;
+                Aabb::surrounding_box(left.bounding_box(t0, t1).unwrap(), right.bounding_box(t0, t1).unwrap())
+            })
+            .collect();
+        let bbox = segments.iter().copied().reduce(Aabb::surrounding_box).unwrap();
+        if segments.iter().all(|b| b.min.a == bbox.min.a && b.max.a == bbox.max.a) {
+            segments.clear();
+        }
         BVHNode {
             left: left.clone(),
             right: right.clone(),
-            bbox: Aabb::surrounding_box(
-                left.bounding_box(time0, time1).unwrap(),
-                right.bounding_box(time0, time1).unwrap(),
-            ),
+            bbox,
+            segments,
+            time0,
+            time1,
+        }
+    }
+
+    // Position of `time` in segments from the start of the interval.
+    fn segment_position(&self, time: f64) -> f64 {
+        (time - self.time0) / (self.time1 - self.time0) * TIME_SEGMENTS as f64
+    }
+
+    // The bounds at `time`. Outside the interval the node was built for the
+    // whole box stands in, as it does in `bounding_box`.
+    fn box_at(&self, time: f64) -> Aabb {
+        if self.segments.is_empty() || !(self.time0..=self.time1).contains(&time) {
+            return self.bbox;
         }
+        self.segments[(self.segment_position(time) as usize).min(TIME_SEGMENTS - 1)]
     }
 
     pub fn new_from_list(objects: HittableList, time0: f64, time1: f64) -> BVHNode {
@@ -33,26 +67,38 @@ impl BVHNode {
     }
 
     pub fn new_from_vec(mut objects: Vec<Arc<dyn Hittable>>, time0: f64, time1: f64) -> BVHNode {
-        let axis = random_int(0, 2);
-        let comparator = if axis == 0 {
-            box_x_compare
-        } else if axis == 1 {
-            box_y_compare
+        // split where the objects are halfway through the shutter interval,
+        // along the axis their centres spread the most
+        let time = 0.5 * (time0 + time1);
+        let centres: Vec<Point3> = objects.iter().map(|o| centre(o.as_ref(), time)).collect();
+        let mut lo = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut hi = -lo;
+        for c in &centres {
+            for a in 0..3 {
+                lo[a] = lo[a].min(c[a]);
+                hi[a] = hi[a].max(c[a]);
+            }
+        }
+        let extent = hi - lo;
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+            0
+        } else if extent.y() >= extent.z() {
+            1
         } else {
-            box_z_compare
+            2
         };
         let object_span = objects.len();
 
         if object_span == 1 {
             BVHNode::new(objects[0].clone(), objects[0].clone(), time0, time1)
         } else if object_span == 2 {
-            if comparator(objects[0].clone(), objects[1].clone()).is_gt() {
+            if box_compare(objects[0].as_ref(), objects[1].as_ref(), axis, time).is_gt() {
                 BVHNode::new(objects[0].clone(), objects[1].clone(), time0, time1)
             } else {
                 BVHNode::new(objects[1].clone(), objects[0].clone(), time0, time1)
             }
         } else {
-            objects.sort_by(|a, b| comparator(a.clone(), b.clone()));
+            objects.sort_by(|a, b| box_compare(a.as_ref(), b.as_ref(), axis, time));
             let mid = object_span / 2;
             BVHNode::new(
                 Arc::new(BVHNode::new_from_vec(
@@ -70,12 +116,13 @@ impl BVHNode {
 
 impl Hittable for BVHNode {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !self.bbox.hit(r, t_min, t_max) {
+        if !self.box_at(r.time).hit(r, t_min, t_max) {
             return None;
         }
-        let hit_left = self.left.hit(r, t_min, t_max);
+        let hit_left = hit_alpha_tested(self.left.as_ref(), r, t_min, t_max);
 
-        let hit_right = self.right.hit(
+        let hit_right = hit_alpha_tested(
+            self.right.as_ref(),
             r,
             t_min,
             if let Some(l) = hit_left.as_ref() {
@@ -86,26 +133,89 @@ impl Hittable for BVHNode {
         );
         hit_right.or(hit_left)
     }
-    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
-        Some(self.bbox)
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        if self.segments.is_empty() || time0 < self.time0 || time1 > self.time1 {
+            return Some(self.bbox);
+        }
+        // a time on the boundary between two segments only needs the first
+        let i0 = (self.segment_position(time0) as usize).min(TIME_SEGMENTS - 1);
+        let i1 = ((self.segment_position(time1) - 1e-9).ceil().max(1.0) as usize - 1).clamp(i0, TIME_SEGMENTS - 1);
+        self.segments[i0..=i1].iter().copied().reduce(Aabb::surrounding_box)
     }
 }
 
-fn box_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>, axis: usize) -> std::cmp::Ordering {
-    let box_a = a.bounding_box(0., 0.).unwrap();
-    let box_b = b.bounding_box(0., 0.).unwrap();
-
-    box_a.min.a[axis].partial_cmp(&box_b.min.a[axis]).unwrap()
+fn centre(h: &dyn Hittable, time: f64) -> Point3 {
+    let b = h.bounding_box(time, time).unwrap();
+    0.5 * (b.min + b.max)
 }
 
-fn box_x_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> std::cmp::Ordering {
-    box_compare(a, b, 0)
+fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize, time: f64) -> std::cmp::Ordering {
+    centre(a, time)[axis].partial_cmp(&centre(b, time)[axis]).unwrap()
 }
 
-fn box_y_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> std::cmp::Ordering {
-    box_compare(a, b, 1)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::*;
+    use crate::color::Color;
+    use crate::material::Lambertian;
+    use crate::sphere::{MovingSphere, Sphere};
+    use crate::vec3::*;
 
-fn box_z_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> std::cmp::Ordering {
-    box_compare(a, b, 2)
+    fn contains(outer: &Aabb, inner: &Aabb) -> bool {
+        (0..3).all(|a| outer.min.a[a] <= inner.min.a[a] + 1e-9 && outer.max.a[a] >= inner.max.a[a] - 1e-9)
+    }
+
+    #[test]
+    fn segments_bound_their_children_over_each_part_of_the_interval() {
+        let grey = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let (start, end) = (Point3::new(-4.0, 1.0, 0.0), Point3::new(4.0, 1.0, 0.0));
+        let sphere: Arc<dyn Hittable> = Arc::new(MovingSphere::new(start, end, 0.0, 2.0, 0.5, grey.clone()));
+        let unit = Vec3::new(1.0, 1.0, 1.0);
+        let path = Animation::new(
+            vec![
+                Keyframe::new(0.0, Vec3::new(0.0, 0.0, -3.0), Vec3::new(0.0, 0.0, 0.0), unit),
+                Keyframe::new(2.0, Vec3::new(0.0, 4.0, 3.0), Vec3::new(0.0, 180.0, 0.0), unit),
+            ],
+            Interpolation::Bezier,
+        );
+        let ball = Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.5, grey));
+        let animated: Arc<dyn Hittable> = Arc::new(Animated::new(ball, Arc::new(path)));
+        let node = BVHNode::new(sphere.clone(), animated.clone(), 0.0, 2.0);
+
+        assert_eq!(node.segments.len(), TIME_SEGMENTS);
+        for (i, segment) in node.segments.iter().enumerate() {
+            let t0 = 2.0 * i as f64 / TIME_SEGMENTS as f64;
+            let t1 = 2.0 * (i + 1) as f64 / TIME_SEGMENTS as f64;
+            for child in [&sphere, &animated] {
+                assert!(contains(segment, &child.bounding_box(t0, t1).unwrap()));
+            }
+            // each segment only covers its own part of the motion
+            assert!(!contains(segment, &node.bbox));
+            assert!(contains(&node.bbox, segment));
+        }
+        // outside the interval the whole box is used rather than none
+        assert!(contains(&node.box_at(3.0), &node.bbox));
+        assert!(contains(&node.box_at(-1.0), &node.bbox));
+    }
+
+    #[test]
+    fn primitives_keep_their_own_ids_under_the_tree() {
+        let grey = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        for i in 0..6 {
+            list.add(Arc::new(Sphere::new(Point3::new(3.0 * i as f64, 0.0, 0.0), 1.0, grey.clone())));
+        }
+        let node = BVHNode::new_from_list(list, 0.0, 1.0);
+        let ids: Vec<u32> = (0..6)
+            .map(|i| {
+                let r = Ray::new(Point3::new(3.0 * i as f64, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.5);
+                node.hit(&r, 0.001, f64::INFINITY).unwrap().object_id
+            })
+            .collect();
+        for (i, id) in ids.iter().enumerate() {
+            assert!(*id > 0);
+            assert!(!ids[..i].contains(id), "{:?}", ids);
+        }
+    }
 }
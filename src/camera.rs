@@ -1,21 +1,190 @@
+use crate::color::luminance;
 use crate::ray::*;
 use crate::utils::*;
 use crate::vec3::*;
+use image::io::Reader as ImageReader;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
-pub struct Camera {
-    origin: Point3,
-    lower_left_corner: Point3,
-    horizontal: Vec3,
-    vertical: Vec3,
+// Turns a film position (s, t) in [0, 1]^2, t running bottom to top, into a
+// ray leaving the camera, or None when the lens blocks it.
+pub trait Camera: Send + Sync {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray>;
+}
+
+// Camera body and lens in physical units: millimetres, seconds and ISO.
+#[derive(Clone, Copy)]
+pub struct CameraSettings {
+    pub focal_length: f64,
+    pub f_stop: f64,
+    pub sensor_width: f64,
+    pub sensor_height: f64,
+    pub shutter: f64,
+    pub iso: f64,
+}
+
+impl CameraSettings {
+    pub fn new(focal_length: f64, f_stop: f64, sensor_width: f64, sensor_height: f64, shutter: f64, iso: f64) -> CameraSettings {
+        CameraSettings {
+            focal_length,
+            f_stop,
+            sensor_width,
+            sensor_height,
+            shutter,
+            iso,
+        }
+    }
+
+    // Vertical field of view in degrees of the largest image of the given
+    // aspect ratio that fits on the sensor.
+    pub fn vfov(&self, aspect_ratio: f64) -> f64 {
+        let height = self.sensor_height.min(self.sensor_width / aspect_ratio);
+        2.0 * (height / (2.0 * self.focal_length)).atan() * 180.0 / PI
+    }
+
+    // Diameter of the entrance pupil in world units.
+    pub fn aperture(&self, units_per_mm: f64) -> f64 {
+        self.focal_length / self.f_stop * units_per_mm
+    }
+
+    // Exposure in stops relative to 1 s at f/1 and ISO 100.
+    pub fn exposure_ev(&self) -> f64 {
+        (self.shutter * self.iso / 100.0 / (self.f_stop * self.f_stop)).log2()
+    }
+}
+
+// The shape of the opening in the lens, which is the shape of out of focus
+// highlights.
+#[derive(Clone)]
+pub enum ApertureShape {
+    Circle,
+    // a regular polygon, rotated by `rotation` degrees
+    Blades { count: u32, rotation: f64 },
+    Mask(Arc<ApertureMask>),
+}
+
+impl ApertureShape {
+    // A point on the aperture, within [-1, 1]^2.
+    fn sample(&self) -> (f64, f64) {
+        match self {
+            ApertureShape::Circle => {
+                let p = Vec3::random_in_unit_disk();
+                (p.x(), p.y())
+            }
+            ApertureShape::Blades { count, rotation } => {
+                // a uniform point in one of the polygon's triangles
+                let n = (*count).max(3) as f64;
+                let blade = (random_f64() * n).floor();
+                let (mut a, mut b) = (random_f64(), random_f64());
+                if a + b > 1.0 {
+                    (a, b) = (1.0 - a, 1.0 - b);
+                }
+                let theta0 = degrees_to_radians(*rotation) + blade * 2.0 * PI / n;
+                let theta1 = theta0 + 2.0 * PI / n;
+                (a * theta0.cos() + b * theta1.cos(), a * theta0.sin() + b * theta1.sin())
+            }
+            ApertureShape::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+// A greyscale image of the aperture; brighter pixels let more light through.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn new(p: &std::path::Path) -> ApertureMask {
+        let i = ImageReader::open(p).unwrap().decode().unwrap().to_rgb8();
+        let (width, height) = (i.width() as usize, i.height() as usize);
+        let mut cdf = Vec::with_capacity(width * height + 1);
+        let mut total = 0.0;
+        cdf.push(total);
+        for p in i.pixels() {
+            total += luminance(Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64) / 255.0);
+            cdf.push(total);
+        }
+        ApertureMask { width, height, cdf }
+    }
+
+    fn sample(&self) -> (f64, f64) {
+        let target = random_f64() * self.cdf[self.cdf.len() - 1];
+        let i = self.cdf.partition_point(|&c| c <= target).clamp(1, self.cdf.len() - 1) - 1;
+        let x = (i % self.width) as f64 + random_f64();
+        let y = (i / self.width) as f64 + random_f64();
+        // image rows run top down
+        (2.0 * x / self.width as f64 - 1.0, 1.0 - 2.0 * y / self.height as f64)
+    }
+}
+
+// How the scene picks its camera; the view and shutter are set separately.
+#[derive(Clone, Copy)]
+pub enum Projection {
+    Perspective,
+    // `height` is the world-space height of the view
+    Orthographic { height: f64 },
+    // equidistant, with `fov` degrees across the width of the image
+    Fisheye { fov: f64 },
+    // 360° by 180°, for a 2:1 image
+    Equirectangular,
+    // six 90° faces in a 3 x 2 grid, for a 3:2 image
+    CubeMap,
+    // traced through the lens prescription in the given file
+    Realistic { prescription: &'static str },
+}
+
+// Position, orientation and shutter shared by every projection.
+pub struct CameraFrame {
+    pub origin: Point3,
     u: Vec3,
     v: Vec3,
-    //w: Vec3,
-    lens_radius: f64,
+    w: Vec3,
     time0: f64,
     time1: f64,
 }
 
-impl Camera {
+impl CameraFrame {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, time0: f64, time1: f64) -> CameraFrame {
+        let w = (lookfrom - lookat).unit_vector();
+        let u = vup.cross(w).unit_vector();
+        let v = w.cross(u);
+        CameraFrame {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            time0,
+            time1,
+        }
+    }
+
+    // Camera space (right, up, forward) to world space.
+    pub fn to_world(&self, x: f64, y: f64, z: f64) -> Vec3 {
+        x * self.u + y * self.v - z * self.w
+    }
+
+    // The shutter is open over [time0, time1] of the scene's clock.
+    pub fn ray(&self, orig: Point3, dir: Vec3) -> Option<Ray> {
+        let time = self.time0 + random_f64() * (self.time1 - self.time0);
+        Some(Ray::new(orig, dir, time))
+    }
+}
+
+// Thin-lens perspective camera.
+pub struct PerspectiveCamera {
+    frame: CameraFrame,
+    lower_left_corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    lens_radius: f64,
+    aspect_ratio: f64,
+    shape: ApertureShape,
+    cat_eye: f64,
+}
+
+impl PerspectiveCamera {
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -26,44 +195,259 @@ impl Camera {
         focus_dist: f64,
         time0: f64,
         time1: f64,
-    ) -> Camera {
-        let theta = vfov * std::f64::consts::PI / 180.;
+    ) -> PerspectiveCamera {
+        let theta = vfov * PI / 180.;
         let h = (theta / 2.).tan();
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = (lookfrom - lookat).unit_vector();
-        let u = vup.cross(w).unit_vector();
-        let v = w.cross(u);
+        let frame = CameraFrame::new(lookfrom, lookat, vup, time0, time1);
+        let horizontal = focus_dist * viewport_width * frame.u;
+        let vertical = focus_dist * viewport_height * frame.v;
+        let lower_left_corner = frame.origin - horizontal / 2. - vertical / 2. - focus_dist * frame.w;
 
-        let origin = lookfrom;
-        let horizontal = focus_dist * viewport_width * u;
-        let vertical = focus_dist * viewport_height * v;
-        let lower_left_corner = origin - horizontal / 2. - vertical / 2. - focus_dist * w;
-
-        let lens_radius = aperture / 2.;
-
-        Camera {
-            origin: origin,
-            horizontal: horizontal,
-            vertical: vertical,
-            lower_left_corner: lower_left_corner,
-            u: u,
-            v: v,
-            //w: w,
-            lens_radius: lens_radius,
-            time0: time0,
-            time1: time1,
+        PerspectiveCamera {
+            frame,
+            lower_left_corner,
+            horizontal,
+            vertical,
+            lens_radius: aperture / 2.,
+            aspect_ratio,
+            shape: ApertureShape::Circle,
+            cat_eye: 0.0,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
-        let offset = self.u * rd.x() + self.v * rd.y();
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            random_range(self.time0, self.time1),
+    // Field of view and aperture from the camera settings. The shutter is
+    // open from time 0 to 1 like any other camera's; its length in seconds
+    // only sets the exposure.
+    pub fn new_physical(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        settings: &CameraSettings,
+        aspect_ratio: f64,
+        units_per_mm: f64,
+        focus_dist: f64,
+    ) -> PerspectiveCamera {
+        PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            settings.vfov(aspect_ratio),
+            aspect_ratio,
+            settings.aperture(units_per_mm),
+            focus_dist,
+            0.0,
+            1.0,
         )
     }
+
+    pub fn with_aperture_shape(self, shape: ApertureShape) -> PerspectiveCamera {
+        PerspectiveCamera { shape, ..self }
+    }
+
+    // Off axis, the lens barrel clips the aperture into a cat's eye; at 1 the
+    // corners of the image see no light at all.
+    pub fn with_cat_eye(self, cat_eye: f64) -> PerspectiveCamera {
+        PerspectiveCamera { cat_eye, ..self }
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let (a, b) = self.shape.sample();
+        if self.cat_eye > 0.0 {
+            // the barrel is an aperture-sized circle that slides across it
+            // towards the edge of the image
+            let half_diagonal = (self.aspect_ratio * self.aspect_ratio + 1.0).sqrt() / 2.0;
+            let cx = 2.0 * self.cat_eye * (s - 0.5) * self.aspect_ratio / half_diagonal;
+            let cy = 2.0 * self.cat_eye * (t - 0.5) / half_diagonal;
+            if (a - cx).powi(2) + (b - cy).powi(2) > 1.0 {
+                return None;
+            }
+        }
+        let offset = self.lens_radius * (self.frame.u * a + self.frame.v * b);
+        let origin = self.frame.origin + offset;
+        self.frame
+            .ray(origin, self.lower_left_corner + s * self.horizontal + t * self.vertical - origin)
+    }
+}
+
+// Parallel rays from a view rectangle centred on `lookfrom`.
+pub struct OrthographicCamera {
+    frame: CameraFrame,
+    width: f64,
+    height: f64,
+}
+
+impl OrthographicCamera {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, height: f64, aspect_ratio: f64, time0: f64, time1: f64) -> OrthographicCamera {
+        OrthographicCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup, time0, time1),
+            width: aspect_ratio * height,
+            height,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let origin = self.frame.origin + self.frame.to_world((s - 0.5) * self.width, (t - 0.5) * self.height, 0.0);
+        self.frame.ray(origin, -self.frame.w)
+    }
+}
+
+// Equidistant fisheye: the angle from the view axis grows linearly with the
+// distance from the centre of the image.
+pub struct FisheyeCamera {
+    frame: CameraFrame,
+    half_fov: f64,
+    aspect_ratio: f64,
+}
+
+impl FisheyeCamera {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, fov: f64, aspect_ratio: f64, time0: f64, time1: f64) -> FisheyeCamera {
+        FisheyeCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup, time0, time1),
+            half_fov: degrees_to_radians(fov) / 2.0,
+            aspect_ratio,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        // in units of half the image width
+        let x = 2.0 * s - 1.0;
+        let y = (2.0 * t - 1.0) / self.aspect_ratio;
+        let r = (x * x + y * y).sqrt();
+        let theta = r * self.half_fov;
+        let (sin_phi, cos_phi) = if r > 0.0 { (y / r, x / r) } else { (0.0, 0.0) };
+        let dir = self.frame.to_world(theta.sin() * cos_phi, theta.sin() * sin_phi, theta.cos());
+        self.frame.ray(self.frame.origin, dir)
+    }
+}
+
+// Latitude-longitude panorama with `lookat` in the middle of the image.
+pub struct EquirectangularCamera {
+    frame: CameraFrame,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, time0: f64, time1: f64) -> EquirectangularCamera {
+        EquirectangularCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup, time0, time1),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let phi = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+        let dir = self
+            .frame
+            .to_world(latitude.cos() * phi.sin(), latitude.sin(), latitude.cos() * phi.cos());
+        self.frame.ray(self.frame.origin, dir)
+    }
+}
+
+// Cube map faces laid out as
+//   +X -X +Y
+//   -Y +Z -Z
+// in camera space, so +Z looks at `lookat` and +Y is up.
+pub struct CubeMapCamera {
+    frame: CameraFrame,
+}
+
+impl CubeMapCamera {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, time0: f64, time1: f64) -> CubeMapCamera {
+        CubeMapCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup, time0, time1),
+        }
+    }
+}
+
+impl Camera for CubeMapCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let column = ((s * 3.0) as usize).min(2);
+        let row = (((1.0 - t) * 2.0) as usize).min(1);
+        // position on the face in [-1, 1], right and up as seen from inside
+        let a = 2.0 * (s * 3.0 - column as f64) - 1.0;
+        let b = 2.0 * (t * 2.0 - (1 - row) as f64) - 1.0;
+        let (x, y, z) = match (row, column) {
+            (0, 0) => (1.0, b, -a),
+            (0, 1) => (-1.0, b, a),
+            (0, _) => (a, 1.0, -b),
+            (_, 0) => (a, -1.0, b),
+            (_, 1) => (a, b, 1.0),
+            _ => (-a, b, -1.0),
+        };
+        let dir = self.frame.to_world(x, y, z);
+        self.frame.ray(self.frame.origin, dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direction(camera: &dyn Camera, s: f64, t: f64) -> Vec3 {
+        camera.get_ray(s, t).unwrap().dir.unit_vector()
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn projections_look_at_lookat_from_the_centre() {
+        let (from, at, up) = (Point3::new(1.0, 2.0, 3.0), Point3::new(1.0, 2.0, -7.0), Vec3::new(0.0, 1.0, 0.0));
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let cameras: Vec<Box<dyn Camera>> = vec![
+            Box::new(PerspectiveCamera::new(from, at, up, 40.0, 1.5, 0.0, 10.0, 0.0, 1.0)),
+            Box::new(OrthographicCamera::new(from, at, up, 4.0, 1.5, 0.0, 1.0)),
+            Box::new(FisheyeCamera::new(from, at, up, 180.0, 1.5, 0.0, 1.0)),
+            Box::new(EquirectangularCamera::new(from, at, up, 0.0, 1.0)),
+        ];
+        for camera in &cameras {
+            assert!(close(direction(camera.as_ref(), 0.5, 0.5), forward));
+        }
+        // the +Z face is the middle of the bottom row
+        assert!(close(direction(&CubeMapCamera::new(from, at, up, 0.0, 1.0), 0.5, 0.25), forward));
+    }
+
+    #[test]
+    fn wide_projections_cover_the_sphere() {
+        let (from, at, up) = (Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0));
+        let panorama = EquirectangularCamera::new(from, at, up, 0.0, 1.0);
+        assert!(close(direction(&panorama, 0.0, 0.5), Vec3::new(0.0, 0.0, 1.0)));
+        assert!(close(direction(&panorama, 0.75, 0.5), Vec3::new(1.0, 0.0, 0.0)));
+        assert!(close(direction(&panorama, 0.5, 1.0), Vec3::new(0.0, 1.0, 0.0)));
+        // a 180° fisheye sees sideways at the edge of the image
+        let fisheye = FisheyeCamera::new(from, at, up, 180.0, 1.0, 0.0, 1.0);
+        assert!(close(direction(&fisheye, 1.0, 0.5), Vec3::new(1.0, 0.0, 0.0)));
+
+        let cube = CubeMapCamera::new(from, at, up, 0.0, 1.0);
+        let centres = [
+            (1.0 / 6.0, 0.75),
+            (0.5, 0.75),
+            (5.0 / 6.0, 0.75),
+            (1.0 / 6.0, 0.25),
+            (0.5, 0.25),
+            (5.0 / 6.0, 0.25),
+        ];
+        let expected = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        for ((s, t), e) in centres.iter().zip(expected) {
+            assert!(close(direction(&cube, *s, *t), e));
+        }
+    }
 }
@@ -1,19 +1,17 @@
 use crate::vec3;
+use crate::vec3::Vec3;
 
 pub type Color = vec3::Vec3;
 
-pub fn write_color(pixel_color: Color, samples_per_pixel: usize) -> [u8; 3] {
-    let mut r = pixel_color.a[0];
-    let mut g = pixel_color.a[1];
-    let mut b = pixel_color.a[2];
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
 
-    let scale = 1.0 / samples_per_pixel as f64;
-    r = (r * scale).sqrt();
-    g = (g * scale).sqrt();
-    b = (b * scale).sqrt();
-    [
-        (256. * r.clamp(0., 0.999)) as u8,
-        (256. * g.clamp(0., 0.999)) as u8,
-        (256. * b.clamp(0., 0.999)) as u8,
-    ]
+// CIE XYZ to linear sRGB (D65)
+pub fn xyz_to_rgb(xyz: Vec3) -> Color {
+    Color::new(
+        3.2406 * xyz.x() - 1.5372 * xyz.y() - 0.4986 * xyz.z(),
+        -0.9689 * xyz.x() + 1.8758 * xyz.y() + 0.0415 * xyz.z(),
+        0.0557 * xyz.x() - 0.2040 * xyz.y() + 1.0570 * xyz.z(),
+    )
 }
@@ -1,7 +1,7 @@
 use crate::aabb::Aabb;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{new_object_id, HitRecord, Hittable};
 use crate::material::Material;
-use crate::ray::Ray;
+use crate::ray::{Point3, Ray};
 use crate::utils::random_f64;
 use crate::vec3::Vec3;
 use std::sync::Arc;
@@ -10,6 +10,7 @@ pub struct ConstantMedium {
     boundary: Arc<dyn Hittable>,
     phase_function: Arc<dyn Material>,
     neg_inv_density: f64,
+    id: u32,
 }
 
 impl ConstantMedium {
@@ -18,6 +19,7 @@ impl ConstantMedium {
             boundary: boundary,
             phase_function: phase_function,
             neg_inv_density: -1.0 / density,
+            id: new_object_id(),
         }
     }
 }
@@ -49,6 +51,8 @@ impl Hittable for ConstantMedium {
                 }
                 let t = rec1.t + hit_distance / ray_length;
                 let mut rec = HitRecord::new(r.at(t), t, 0.0, 0.0, self.phase_function.clone());
+                rec.object_id = self.id;
+                rec.volume = true;
                 rec.front_face = true;
                 rec.normal = Vec3::new(0.0, 0.0, 1.0);
                 if debugging {
@@ -65,4 +69,12 @@ impl Hittable for ConstantMedium {
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         self.boundary.bounding_box(time0, time1)
     }
+    // every scattering point lies inside the boundary, so sampling the
+    // boundary's solid angle covers all directions towards the medium
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.boundary.pdf_value(o, v, time)
+    }
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.boundary.random(o, time)
+    }
 }
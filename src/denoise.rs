@@ -0,0 +1,93 @@
+use crate::aov::Aov;
+use crate::color::*;
+use crate::film::Film;
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al., with the variance
+// guided luminance weight of SVGF). Albedo and normal passes, when the film
+// has them, stop the filter at geometric and texture edges; the albedo is
+// divided out first so only lighting gets blurred.
+pub struct Denoiser {
+    iterations: u32,
+    sigma_luminance: f64,
+    sigma_normal: f64,
+    sigma_albedo: f64,
+}
+
+impl Denoiser {
+    pub fn new(iterations: u32, sigma_luminance: f64, sigma_normal: f64, sigma_albedo: f64) -> Denoiser {
+        Denoiser {
+            iterations,
+            sigma_luminance,
+            sigma_normal,
+            sigma_albedo,
+        }
+    }
+
+    pub fn denoise(&self, film: &Film) -> Vec<Color> {
+        let (w, h) = (film.width as i64, film.height as i64);
+        let albedo = film.aov_pixels(Aov::Albedo);
+        let normal = film.aov_pixels(Aov::Normal);
+        let demodulate = |i: usize| albedo.as_ref().map_or(Color::new(1.0, 1.0, 1.0), |a| clamp_albedo(a[i]));
+
+        let mut color: Vec<Color> = film.pixels().iter().enumerate().map(|(i, c)| ratio(*c, demodulate(i))).collect();
+        let mut variance: Vec<f64> = (0..film.height)
+            .flat_map(|y| (0..film.width).map(move |x| (x, y)))
+            .enumerate()
+            .map(|(i, (x, y))| film.pixel_stats(x, y).variance_of_mean() / luminance(demodulate(i)).powi(2))
+            .collect();
+
+        const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let mut next_color = color.clone();
+            let mut next_variance = variance.clone();
+            for y in 0..h {
+                for x in 0..w {
+                    let p = (y * w + x) as usize;
+                    let lp = luminance(color[p]);
+                    let sigma_l = self.sigma_luminance * variance[p].max(0.0).sqrt() + 1e-6;
+                    let mut sum = Color::new(0.0, 0.0, 0.0);
+                    let mut sum_variance = 0.0;
+                    let mut sum_weight = 0.0;
+                    for (j, kj) in KERNEL.iter().enumerate() {
+                        for (i, ki) in KERNEL.iter().enumerate() {
+                            let qx = x + (i as i64 - 2) * step;
+                            let qy = y + (j as i64 - 2) * step;
+                            if qx < 0 || qy < 0 || qx >= w || qy >= h {
+                                continue;
+                            }
+                            let q = (qy * w + qx) as usize;
+                            let mut weight = ki * kj * (-(lp - luminance(color[q])).abs() / sigma_l).exp();
+                            if let Some(n) = &normal {
+                                weight *= n[p].dot(n[q]).max(0.0).powf(self.sigma_normal);
+                            }
+                            if let Some(a) = &albedo {
+                                weight *= (-(a[p] - a[q]).length_squared() / (self.sigma_albedo * self.sigma_albedo)).exp();
+                            }
+                            sum += weight * color[q];
+                            sum_variance += weight * weight * variance[q];
+                            sum_weight += weight;
+                        }
+                    }
+                    if sum_weight > 0.0 {
+                        next_color[p] = sum / sum_weight;
+                        next_variance[p] = sum_variance / (sum_weight * sum_weight);
+                    }
+                }
+            }
+            color = next_color;
+            variance = next_variance;
+        }
+
+        color.iter().enumerate().map(|(i, c)| *c * demodulate(i)).collect()
+    }
+}
+
+// keeps black surfaces from blowing up the demodulated radiance
+fn clamp_albedo(a: Color) -> Color {
+    Color::new(a.x().max(0.01), a.y().max(0.01), a.z().max(0.01))
+}
+
+fn ratio(a: Color, b: Color) -> Color {
+    Color::new(a.x() / b.x(), a.y() / b.y(), a.z() / b.z())
+}
@@ -0,0 +1,279 @@
+use crate::aabb::Aabb;
+use crate::color::*;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::{Point3, Ray};
+use crate::utils::*;
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+extern crate image;
+use image::codecs::hdr::HdrDecoder;
+
+// What a ray sees when it leaves the scene.
+pub trait Environment: Send + Sync {
+    fn value(&self, dir: &Vec3) -> Color;
+    fn pdf_value(&self, _dir: &Vec3) -> f64 {
+        0.0
+    }
+    fn random(&self) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+    fn is_light(&self) -> bool {
+        false
+    }
+    // radiance averaged over all directions
+    fn average(&self) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+}
+
+pub struct ConstantEnvironment {
+    color: Color,
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Color) -> ConstantEnvironment {
+        ConstantEnvironment { color }
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn value(&self, _dir: &Vec3) -> Color {
+        self.color
+    }
+    fn average(&self) -> Color {
+        self.color
+    }
+}
+
+// Equirectangular radiance map, importance sampled by luminance.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    intensity: f64,
+    average: Color,
+    sin_theta: f64,
+    cos_theta: f64,
+    weights: Vec<f64>,
+    total_weight: f64,
+    marginal_cdf: Vec<f64>,
+    conditional_cdf: Vec<Vec<f64>>,
+}
+
+impl EnvironmentMap {
+    pub fn new(p: &std::path::Path, intensity: f64, rotation: f64) -> EnvironmentMap {
+        let reader = std::io::BufReader::new(std::fs::File::open(p).unwrap());
+        let decoder = HdrDecoder::new(reader).unwrap();
+        let meta = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()
+            .unwrap()
+            .iter()
+            .map(|c| Color::new(c[0] as f64, c[1] as f64, c[2] as f64))
+            .collect();
+        EnvironmentMap::new_from_pixels(pixels, meta.width as usize, meta.height as usize, intensity, rotation)
+    }
+
+    pub fn new_from_pixels(pixels: Vec<Color>, width: usize, height: usize, intensity: f64, rotation: f64) -> EnvironmentMap {
+        let radians = degrees_to_radians(rotation);
+        let mut weights = vec![0.0; width * height];
+        let mut marginal_cdf = vec![0.0; height + 1];
+        let mut conditional_cdf = Vec::with_capacity(height);
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        let mut total_sin = 0.0;
+
+        for j in 0..height {
+            let sin = (PI * (j as f64 + 0.5) / height as f64).sin();
+            let mut cdf = vec![0.0; width + 1];
+            for i in 0..width {
+                sum += sin * pixels[j * width + i];
+                total_sin += sin;
+                let w = luminance(pixels[j * width + i]) * sin;
+                weights[j * width + i] = w;
+                cdf[i + 1] = cdf[i] + w;
+            }
+            marginal_cdf[j + 1] = marginal_cdf[j] + cdf[width];
+            conditional_cdf.push(cdf);
+        }
+
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            intensity,
+            average: intensity / total_sin * sum,
+            sin_theta: radians.sin(),
+            cos_theta: radians.cos(),
+            weights,
+            total_weight: marginal_cdf[height],
+            marginal_cdf,
+            conditional_cdf,
+        }
+    }
+
+    fn to_local(&self, d: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * d.x() - self.sin_theta * d.z(),
+            d.y(),
+            self.sin_theta * d.x() + self.cos_theta * d.z(),
+        )
+    }
+
+    fn to_world(&self, d: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * d.x() + self.sin_theta * d.z(),
+            d.y(),
+            -self.sin_theta * d.x() + self.cos_theta * d.z(),
+        )
+    }
+
+    fn pixel_index(&self, dir: &Vec3) -> (usize, usize) {
+        let d = self.to_local(&dir.unit_vector());
+        let u = (d.z().atan2(d.x()) + PI) / (2.0 * PI);
+        let v = d.y().clamp(-1.0, 1.0).acos() / PI;
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        (i, j)
+    }
+}
+
+// index of the bucket of a (non-normalised) cdf that contains x
+fn sample_cdf(cdf: &[f64], x: f64) -> usize {
+    let target = x * cdf[cdf.len() - 1];
+    let i = cdf.partition_point(|&c| c <= target);
+    i.clamp(1, cdf.len() - 1) - 1
+}
+
+impl Environment for EnvironmentMap {
+    fn value(&self, dir: &Vec3) -> Color {
+        let (i, j) = self.pixel_index(dir);
+        self.intensity * self.pixels[j * self.width + i]
+    }
+
+    fn average(&self) -> Color {
+        self.average
+    }
+
+    fn pdf_value(&self, dir: &Vec3) -> f64 {
+        if self.total_weight <= 0.0 {
+            return 1.0 / (4.0 * PI);
+        }
+        let (i, j) = self.pixel_index(dir);
+        // `random` is uniform in v within a pixel, so the Jacobian takes the
+        // sine of the direction itself rather than of the pixel centre
+        let d = self.to_local(&dir.unit_vector());
+        let sin = (1.0 - d.y() * d.y()).max(0.0).sqrt();
+        if sin <= 0.0 {
+            return 0.0;
+        }
+        let pdf_uv = self.weights[j * self.width + i] * (self.width * self.height) as f64 / self.total_weight;
+        pdf_uv / (2.0 * PI * PI * sin)
+    }
+
+    fn random(&self) -> Vec3 {
+        if self.total_weight <= 0.0 {
+            return Vec3::random_unit_vector();
+        }
+        let j = sample_cdf(&self.marginal_cdf, random_f64());
+        let i = sample_cdf(&self.conditional_cdf[j], random_f64());
+        let u = (i as f64 + random_f64()) / self.width as f64;
+        let v = (j as f64 + random_f64()) / self.height as f64;
+
+        let phi = 2.0 * PI * u - PI;
+        let theta = PI * v;
+        let local = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+        self.to_world(&local)
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+}
+
+// Lets an environment take part in light sampling through the `lights` list.
+// `radius` bounds the scene; its power is what falls onto that sphere.
+pub struct EnvironmentLight {
+    env: Arc<dyn Environment>,
+    radius: f64,
+}
+
+impl EnvironmentLight {
+    pub fn new(env: Arc<dyn Environment>, radius: f64) -> EnvironmentLight {
+        EnvironmentLight { env, radius }
+    }
+}
+
+impl Hittable for EnvironmentLight {
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord> {
+        None
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        None
+    }
+    fn pdf_value(&self, _o: &Point3, v: &Vec3, _time: f64) -> f64 {
+        self.env.pdf_value(v)
+    }
+    fn random(&self, _o: &Vec3, _time: f64) -> Vec3 {
+        self.env.random()
+    }
+    fn power(&self) -> f64 {
+        PI * 4.0 * PI * self.radius * self.radius * luminance(self.env.average())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_uses_the_solid_angle_average() {
+        // bright upper hemisphere, black lower one
+        let (width, height) = (8, 6);
+        let pixels = (0..width * height)
+            .map(|k| {
+                if k < width * height / 2 {
+                    Color::new(2.0, 2.0, 2.0)
+                } else {
+                    Color::new(0.0, 0.0, 0.0)
+                }
+            })
+            .collect();
+        let env: Arc<dyn Environment> = Arc::new(EnvironmentMap::new_from_pixels(pixels, width, height, 1.0, 0.0));
+        assert!((luminance(env.average()) - 1.0).abs() < 1e-9);
+        let light = EnvironmentLight::new(env, 2.0);
+        assert!((light.power() - 16.0 * PI * PI).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pdf_matches_sampling_near_the_pole() {
+        seed_random(1);
+        let (width, height) = (16, 8);
+        let pixels = (0..width * height)
+            .map(|k| {
+                let x = 1.0 + (k % 5) as f64;
+                Color::new(x, x, x)
+            })
+            .collect();
+        let env = EnvironmentMap::new_from_pixels(pixels, width, height, 1.0, 30.0);
+        // the cap over the top half of the first row of pixels
+        let cos_cap = (PI / 16.0).cos();
+        let n = 400_000;
+        let sampled = (0..n).filter(|_| env.random().unit_vector().y() > cos_cap).count() as f64 / n as f64;
+        let integrated = (0..n)
+            .map(|_| {
+                // uniform over the cap
+                let z = cos_cap + (1.0 - cos_cap) * random_f64();
+                let phi = 2.0 * PI * random_f64();
+                let r = (1.0 - z * z).sqrt();
+                env.pdf_value(&Vec3::new(r * phi.cos(), z, r * phi.sin()))
+            })
+            .sum::<f64>()
+            / n as f64
+            * 2.0
+            * PI
+            * (1.0 - cos_cap);
+        assert!((integrated / sampled - 1.0).abs() < 0.02, "{} {}", integrated, sampled);
+    }
+}
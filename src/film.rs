@@ -0,0 +1,614 @@
+use crate::adaptive::{heatmap_color, PixelStats};
+use crate::aov::{Aov, AovBuffer, AovSample};
+use crate::color::*;
+use crate::filter::Filter;
+use crate::tonemap::ToneMapper;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+extern crate image;
+use image::codecs::hdr::HdrEncoder;
+
+// Linear radiance accumulated per pixel, stored top row first. Samples are
+// splatted into tiles with the reconstruction filter and merged here.
+// AOVs are not filtered: each pixel averages its own samples.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub filter: Arc<dyn Filter>,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+    // unweighted sums of the samples landing in each pixel
+    box_sums: Vec<Color>,
+    stats: Vec<PixelStats>,
+    aovs: Vec<Aov>,
+    aov_buffers: Vec<AovBuffer>,
+    layer_names: Vec<(Aov, String)>,
+    pending: BTreeMap<usize, FilmTile>,
+    next_tile: usize,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Arc<dyn Filter>) -> Film {
+        let n = (width * height) as usize;
+        Film {
+            width,
+            height,
+            filter,
+            sums: vec![Color::new(0.0, 0.0, 0.0); n],
+            weights: vec![0.0; n],
+            box_sums: vec![Color::new(0.0, 0.0, 0.0); n],
+            stats: vec![PixelStats::new(); n],
+            aovs: Vec::new(),
+            aov_buffers: Vec::new(),
+            layer_names: Vec::new(),
+            pending: BTreeMap::new(),
+            next_tile: 0,
+        }
+    }
+
+    pub fn with_aovs(mut self, aovs: &[Aov]) -> Film {
+        let n = self.sums.len();
+        self.aovs = aovs.to_vec();
+        // sample counts are already kept in the pixel statistics
+        self.aov_buffers = aovs
+            .iter()
+            .filter(|a| **a != Aov::SampleCount)
+            .map(|a| AovBuffer::new(*a, n))
+            .collect();
+        self
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    // A tile for samples landing in pixels [x0, x1) x [y0, y1), grown by
+    // the filter radius to hold splats into the neighbouring pixels.
+    pub fn tile(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> FilmTile {
+        let margin = (self.filter.radius() - 0.5).ceil().max(0.0) as u32;
+        let x0 = x0.saturating_sub(margin);
+        let y0 = y0.saturating_sub(margin);
+        let x1 = (x1 + margin).min(self.width);
+        let y1 = (y1 + margin).min(self.height);
+        let n = ((x1 - x0) * (y1 - y0)) as usize;
+        FilmTile {
+            x0,
+            y0,
+            x1,
+            y1,
+            filter: self.filter.clone(),
+            sums: vec![Color::new(0.0, 0.0, 0.0); n],
+            weights: vec![0.0; n],
+            box_sums: vec![Color::new(0.0, 0.0, 0.0); n],
+            stats: vec![PixelStats::new(); n],
+            aov_buffers: self.aov_buffers.iter().map(|b| AovBuffer::new(b.aov, n)).collect(),
+        }
+    }
+
+    // Names a pass in the EXR output, e.g. a light group after its lights.
+    pub fn with_layer_name(mut self, aov: Aov, name: &str) -> Film {
+        self.layer_names.push((aov, name.to_string()));
+        self
+    }
+
+    fn layer_name(&self, aov: Aov) -> String {
+        self.layer_names
+            .iter()
+            .find(|(a, _)| *a == aov)
+            .map_or(aov.name(), |(_, n)| n.clone())
+    }
+
+    pub fn pixel_stats(&self, x: u32, y: u32) -> PixelStats {
+        self.stats[self.index(x, y)]
+    }
+
+    // Tile indices restart at zero with every pass.
+    pub fn begin_pass(&mut self) {
+        self.pending.clear();
+        self.next_tile = 0;
+    }
+
+    // Tiles are merged in index order, whatever order they arrive in, so
+    // the floating point sums do not depend on thread scheduling.
+    pub fn submit(&mut self, index: usize, tile: FilmTile) {
+        self.pending.insert(index, tile);
+        while let Some(tile) = self.pending.remove(&self.next_tile) {
+            self.merge(&tile);
+            self.next_tile += 1;
+        }
+    }
+
+    fn merge(&mut self, tile: &FilmTile) {
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let i = self.index(x, y);
+                let j = tile.index(x, y);
+                self.sums[i] += tile.sums[j];
+                self.weights[i] += tile.weights[j];
+                self.box_sums[i] += tile.box_sums[j];
+                self.stats[i].merge(&tile.stats[j]);
+                for (a, b) in self.aov_buffers.iter_mut().zip(&tile.aov_buffers) {
+                    a.merge(i, b, j);
+                }
+            }
+        }
+    }
+
+    // Filters with negative lobes (Mitchell, Lanczos) can leave a pixel with
+    // almost no total weight; it then gets the plain mean of its samples.
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        let count = self.stats[i].count();
+        if self.weights[i] > MIN_WEIGHT_PER_SAMPLE * count as f64 {
+            self.sums[i] / self.weights[i]
+        } else if count > 0 {
+            self.box_sums[i] / count as f64
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    pub fn pixels(&self) -> Vec<Color> {
+        let mut p = Vec::with_capacity(self.sums.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                p.push(self.pixel(x, y));
+            }
+        }
+        p
+    }
+
+    // Samples taken per pixel, scaled against the largest count.
+    pub fn sample_heatmap(&self) -> image::RgbImage {
+        let max = self.stats.iter().map(|s| s.count()).max().unwrap_or(0).max(1) as f64;
+        image::ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let c = heatmap_color(self.stats[self.index(x, y)].count() as f64 / max);
+            image::Rgb([(255.0 * c.x()) as u8, (255.0 * c.y()) as u8, (255.0 * c.z()) as u8])
+        })
+    }
+
+    // Channels of a pass in image order.
+    pub fn aov_channels(&self, aov: Aov) -> Vec<(String, Vec<f32>)> {
+        let names = aov.channels();
+        let mut channels: Vec<Vec<f32>> = vec![Vec::with_capacity(self.sums.len()); names.len()];
+        for i in 0..self.sums.len() {
+            let values = match (aov, self.aov_buffers.iter().find(|b| b.aov == aov)) {
+                (Aov::SampleCount, _) => vec![self.stats[i].count() as f64],
+                (_, Some(b)) => b.value(i),
+                (_, None) => vec![0.0; names.len()],
+            };
+            for (c, v) in channels.iter_mut().zip(values) {
+                c.push(v as f32);
+            }
+        }
+        names.iter().map(|n| n.to_string()).zip(channels).collect()
+    }
+
+    // A three channel pass as colours, if the film records it.
+    pub fn aov_pixels(&self, aov: Aov) -> Option<Vec<Color>> {
+        let b = self.aov_buffers.iter().find(|b| b.aov == aov && aov.channels().len() == 3)?;
+        Some(
+            (0..self.sums.len())
+                .map(|i| {
+                    let v = b.value(i);
+                    Color::new(v[0], v[1], v[2])
+                })
+                .collect(),
+        )
+    }
+
+    // Every enabled pass as "layer.channel", after the beauty channels.
+    pub fn exr_channels(&self) -> Vec<(String, Vec<f32>)> {
+        let mut channels = rgb_channels(&self.pixels());
+        for aov in &self.aovs {
+            for (name, data) in self.aov_channels(*aov) {
+                channels.push((format!("{}.{}", self.layer_name(*aov), name), data));
+            }
+        }
+        channels
+    }
+
+    // Picks the format from the extension. EXR files get every pass as a
+    // layer; the other formats only hold the beauty image.
+    pub fn save(&self, path: &str, tone: &ToneMapper) {
+        if path.ends_with(".exr") {
+            write_exr(Path::new(path), self.width, self.height, &self.exr_channels()).unwrap()
+        } else {
+            save_image(path, self.width, self.height, &self.pixels(), tone)
+        }
+    }
+}
+
+// Floating point formats get the raw radiance; anything else is tone mapped
+// to 8-bit and written by `image`.
+pub fn save_image(path: &str, width: u32, height: u32, pixels: &[Color], tone: &ToneMapper) {
+    let p = Path::new(path);
+    match p.extension().and_then(|e| e.to_str()) {
+        Some("exr") => write_exr(p, width, height, &rgb_channels(pixels)).unwrap(),
+        Some("pfm") => write_pfm(p, width, height, pixels).unwrap(),
+        Some("hdr") => write_hdr(p, width, height, pixels).unwrap(),
+        _ => {
+            let image = image::ImageBuffer::from_fn(width, height, |x, y| image::Rgb(tone.to_rgb8(pixels[(y * width + x) as usize])));
+            image.save(p).unwrap()
+        }
+    }
+}
+
+impl Film {
+    // Writes the accumulated film and how many samples per pixel it holds,
+    // so that an interrupted render can be picked up again. `fingerprint`
+    // identifies the render settings (see `checkpoint_fingerprint`). AOVs
+    // are not saved; after resuming they average only the newer samples.
+    pub fn save_checkpoint(&self, path: &str, fingerprint: u64, samples_taken: usize) -> std::io::Result<()> {
+        let tmp = format!("{}.tmp", path);
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(CHECKPOINT_MAGIC)?;
+        for v in [fingerprint, self.width as u64, self.height as u64, samples_taken as u64] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        for i in 0..self.sums.len() {
+            let (count, mean, m2) = self.stats[i].parts();
+            let (s, b) = (self.sums[i], self.box_sums[i]);
+            for v in [s[0], s[1], s[2], self.weights[i], b[0], b[1], b[2], mean, m2] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+            out.write_all(&(count as u64).to_le_bytes())?;
+        }
+        out.flush()?;
+        drop(out);
+        // replace the old checkpoint only once the new one is complete
+        std::fs::rename(tmp, path)
+    }
+
+    // Returns the film and its samples per pixel, or None when there is no
+    // checkpoint for an image of this size rendered with these settings.
+    pub fn load_checkpoint(path: &str, fingerprint: u64, width: u32, height: u32, filter: Arc<dyn Filter>) -> Option<(Film, usize)> {
+        let mut input = BufReader::new(File::open(path).ok()?);
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic).ok()?;
+        if &magic != CHECKPOINT_MAGIC {
+            return None;
+        }
+        if read_u64(&mut input)? != fingerprint {
+            return None;
+        }
+        let (w, h, samples_taken) = (read_u64(&mut input)?, read_u64(&mut input)?, read_u64(&mut input)?);
+        if w != width as u64 || h != height as u64 {
+            return None;
+        }
+        let mut film = Film::new(width, height, filter);
+        for i in 0..film.sums.len() {
+            let mut v = [0.0; 9];
+            for x in v.iter_mut() {
+                *x = f64::from_bits(read_u64(&mut input)?);
+            }
+            let count = read_u64(&mut input)? as usize;
+            film.sums[i] = Color::new(v[0], v[1], v[2]);
+            film.weights[i] = v[3];
+            film.box_sums[i] = Color::new(v[4], v[5], v[6]);
+            film.stats[i] = PixelStats::from_parts(count, v[7], v[8]);
+        }
+        Some((film, samples_taken as usize))
+    }
+}
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTFILM03";
+
+// FNV-1a of a description of everything that changes the image, e.g. the
+// scene, samples per pixel, seed, sampler and filter.
+pub fn checkpoint_fingerprint(settings: &str) -> u64 {
+    settings
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+const MIN_WEIGHT_PER_SAMPLE: f64 = 1e-3;
+
+fn read_u64(input: &mut impl Read) -> Option<u64> {
+    let mut b = [0u8; 8];
+    input.read_exact(&mut b).ok()?;
+    Some(u64::from_le_bytes(b))
+}
+
+pub struct FilmTile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    filter: Arc<dyn Filter>,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+    box_sums: Vec<Color>,
+    stats: Vec<PixelStats>,
+    aov_buffers: Vec<AovBuffer>,
+}
+
+impl FilmTile {
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y0) * (self.x1 - self.x0) + x - self.x0) as usize
+    }
+
+    pub fn record_stats(&mut self, x: u32, y: u32, stats: &PixelStats) {
+        let i = self.index(x, y);
+        self.stats[i].merge(stats);
+    }
+
+    pub fn add_aov_sample(&mut self, x: u32, y: u32, sample: &AovSample) {
+        let i = self.index(x, y);
+        for b in self.aov_buffers.iter_mut() {
+            b.add(i, sample);
+        }
+    }
+
+    // Splats a sample at continuous film position (x, y) to every pixel
+    // whose centre lies within the filter radius, and adds it unweighted to
+    // the pixel it lands in.
+    pub fn add_sample(&mut self, x: f64, y: f64, radiance: Color) {
+        let (bx, by) = (x.floor(), y.floor());
+        if bx >= self.x0 as f64 && bx < self.x1 as f64 && by >= self.y0 as f64 && by < self.y1 as f64 {
+            let i = self.index(bx as u32, by as u32);
+            self.box_sums[i] += radiance;
+        }
+        let r = self.filter.radius();
+        let px0 = ((x - 0.5 - r).ceil().max(self.x0 as f64)) as u32;
+        let py0 = ((y - 0.5 - r).ceil().max(self.y0 as f64)) as u32;
+        let px1 = ((x - 0.5 + r).floor() + 1.0).clamp(0.0, self.x1 as f64) as u32;
+        let py1 = ((y - 0.5 + r).floor() + 1.0).clamp(0.0, self.y1 as f64) as u32;
+        for py in py0..py1 {
+            for px in px0..px1 {
+                let w = self.filter.evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                let i = self.index(px, py);
+                self.sums[i] += w * radiance;
+                self.weights[i] += w;
+            }
+        }
+    }
+}
+
+pub fn rgb_channels(pixels: &[Color]) -> Vec<(String, Vec<f32>)> {
+    ["R", "G", "B"]
+        .iter()
+        .enumerate()
+        .map(|(c, name)| (name.to_string(), pixels.iter().map(|p| p[c] as f32).collect()))
+        .collect()
+}
+
+fn exr_attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+// Uncompressed scanline OpenEXR with 32-bit float channels. Channel names
+// may carry a layer prefix ("albedo.R"); they are written in sorted order
+// as the format requires.
+pub fn write_exr(path: &Path, width: u32, height: u32, channels: &[(String, Vec<f32>)]) -> std::io::Result<()> {
+    let mut channels: Vec<&(String, Vec<f32>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut chlist = Vec::new();
+    for (name, _) in &channels {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    exr_attribute(&mut header, "channels", "chlist", &chlist);
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    // one scanline per block, preceded by a table of block offsets
+    let line_size = channels.len() * width as usize * 4;
+    let first_block = header.len() + height as usize * 8;
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&header)?;
+    for y in 0..height as usize {
+        out.write_all(&((first_block + y * (8 + line_size)) as u64).to_le_bytes())?;
+    }
+    for y in 0..height as usize {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        for (_, data) in &channels {
+            for v in &data[y * width as usize..(y + 1) * width as usize] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+// Little-endian colour PFM; rows are stored bottom to top.
+pub fn write_pfm(path: &Path, width: u32, height: u32, pixels: &[Color]) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    for y in (0..height as usize).rev() {
+        for p in &pixels[y * width as usize..(y + 1) * width as usize] {
+            for c in 0..3 {
+                out.write_all(&(p[c] as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+pub fn write_hdr(path: &Path, width: u32, height: u32, pixels: &[Color]) -> std::io::Result<()> {
+    let data: Vec<image::Rgb<f32>> = pixels.iter().map(|p| image::Rgb([p[0] as f32, p[1] as f32, p[2] as f32])).collect();
+    HdrEncoder::new(BufWriter::new(File::create(path)?))
+        .encode(&data, width as usize, height as usize)
+        .map_err(std::io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::LanczosFilter;
+
+    fn read_cstr(bytes: &[u8], at: &mut usize) -> String {
+        let end = *at + bytes[*at..].iter().position(|&b| b == 0).unwrap();
+        let s = String::from_utf8(bytes[*at..end].to_vec()).unwrap();
+        *at = end + 1;
+        s
+    }
+
+    fn le_i32(bytes: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn negative_lobes_fall_back_to_the_plain_mean() {
+        let mut film = Film::new(8, 8, Arc::new(LanczosFilter::new(3.0)));
+        let mut tile = film.tile(0, 0, 8, 8);
+        // one sample in pixel (2, 2), and a bright neighbour in the
+        // negative lobe 1.5 pixels away
+        tile.add_sample(2.5, 2.5, Color::new(1.0, 1.0, 1.0));
+        let mut stats = PixelStats::new();
+        stats.add(Color::new(1.0, 1.0, 1.0));
+        tile.record_stats(2, 2, &stats);
+        for _ in 0..10 {
+            tile.add_sample(4.0, 2.5, Color::new(5.0, 5.0, 5.0));
+        }
+        film.begin_pass();
+        film.submit(0, tile);
+        assert!(film.weights[film.index(2, 2)] < 0.0);
+        assert!((film.pixel(2, 2) - Color::new(1.0, 1.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn checkpoints_round_trip_only_for_the_same_settings() {
+        let filter: Arc<dyn Filter> = Arc::new(LanczosFilter::new(3.0));
+        let mut film = Film::new(6, 4, filter.clone());
+        let mut tile = film.tile(0, 0, 6, 4);
+        for k in 0..24 {
+            let c = Color::new(k as f64, 0.5, 2.0);
+            tile.add_sample(0.3 + (k % 6) as f64, 0.7 + (k / 6) as f64, c);
+            let mut stats = PixelStats::new();
+            stats.add(c);
+            tile.record_stats(k % 6, k / 6, &stats);
+        }
+        film.begin_pass();
+        film.submit(0, tile);
+
+        let path = std::env::temp_dir().join(format!("film_test_{}.film", std::process::id()));
+        let path = path.to_str().unwrap();
+        let fingerprint = checkpoint_fingerprint("scene 5 spp 64");
+        film.save_checkpoint(path, fingerprint, 64).unwrap();
+        let (loaded, taken) = Film::load_checkpoint(path, fingerprint, 6, 4, filter.clone()).unwrap();
+        assert_eq!(taken, 64);
+        for (p, q) in film.pixels().iter().zip(loaded.pixels()) {
+            assert_eq!(p.a, q.a);
+        }
+        assert_eq!(loaded.pixel_stats(2, 1).parts(), film.pixel_stats(2, 1).parts());
+
+        let other = checkpoint_fingerprint("scene 5 spp 128");
+        assert!(Film::load_checkpoint(path, other, 6, 4, filter.clone()).is_none());
+        assert!(Film::load_checkpoint(path, fingerprint, 4, 6, filter).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn exr_holds_sorted_channels_per_scanline() {
+        let (width, height) = (3, 2);
+        let pixels: Vec<Color> = (0..6).map(|i| Color::new(i as f64, 10.0 + i as f64, 100.0 + i as f64)).collect();
+        let mut channels = rgb_channels(&pixels);
+        channels.push(("albedo.R".to_string(), vec![0.5; 6]));
+        let path = std::env::temp_dir().join(format!("film_test_{}.exr", std::process::id()));
+        write_exr(&path, width, height, &channels).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(bytes[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        let mut at = 8;
+        let mut names = Vec::new();
+        loop {
+            let name = read_cstr(&bytes, &mut at);
+            if name.is_empty() {
+                break;
+            }
+            let kind = read_cstr(&bytes, &mut at);
+            let size = le_i32(&bytes, at) as usize;
+            at += 4;
+            if kind == "chlist" {
+                let mut c = at;
+                while bytes[c] != 0 {
+                    names.push(read_cstr(&bytes, &mut c));
+                    assert_eq!(le_i32(&bytes, c), 2);
+                    c += 16;
+                }
+            }
+            at += size;
+        }
+        assert_eq!(names, ["B", "G", "R", "albedo.R"]);
+
+        // offset table, then each line: y, byte count, channels in order
+        let offset = u64::from_le_bytes(bytes[at + 8..at + 16].try_into().unwrap()) as usize;
+        assert_eq!(le_i32(&bytes, offset), 1);
+        assert_eq!(le_i32(&bytes, offset + 4), 4 * 4 * 3);
+        let value = |channel: usize, x: usize| f32::from_le_bytes(bytes[offset + 8 + 4 * (channel * 3 + x)..][..4].try_into().unwrap());
+        assert_eq!(value(0, 2), 105.0);
+        assert_eq!(value(1, 0), 13.0);
+        assert_eq!(value(2, 1), 4.0);
+        assert_eq!(value(3, 1), 0.5);
+        assert_eq!(bytes.len(), offset + 8 + 4 * 4 * 3);
+    }
+
+    #[test]
+    fn hdr_round_trips() {
+        let pixels: Vec<Color> = (0..4).map(|i| Color::new(0.25 * i as f64, 2.0, 40.0)).collect();
+        let path = std::env::temp_dir().join(format!("film_test_{}.hdr", std::process::id()));
+        write_hdr(&path, 2, 2, &pixels).unwrap();
+        let decoder = image::codecs::hdr::HdrDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+        let read = decoder.read_image_hdr().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for (p, q) in pixels.iter().zip(read) {
+            for c in 0..3 {
+                assert!((p[c] as f32 - q[c]).abs() <= 0.01 * p[c] as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn pfm_is_little_endian_bottom_row_first() {
+        // 2 x 3, with every value distinct
+        let (width, height) = (2, 3);
+        let pixels: Vec<Color> = (0..6).map(|i| Color::new(i as f64, 10.0 + i as f64, 0.5 * i as f64)).collect();
+        let path = std::env::temp_dir().join(format!("film_test_{}.pfm", std::process::id()));
+        write_pfm(&path, width, height, &pixels).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"PF\n2 3\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let floats: Vec<f32> = bytes[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(floats.len(), 3 * pixels.len());
+        for (i, rgb) in floats.chunks(3).enumerate() {
+            // file row 0 is the bottom row of the image
+            let (x, file_row) = (i % width as usize, i / width as usize);
+            let p = pixels[(height as usize - 1 - file_row) * width as usize + x];
+            assert_eq!(rgb, [p[0] as f32, p[1] as f32, p[2] as f32]);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::f64::consts::PI;
+
+// Pixel reconstruction filter, centred on the pixel and evaluated at an
+// offset in pixels. It is zero outside `radius` along either axis.
+pub trait Filter: Send + Sync + std::fmt::Debug {
+    fn radius(&self) -> f64;
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
+
+#[derive(Debug)]
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> BoxFilter {
+        BoxFilter { radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, _x: f64, _y: f64) -> f64 {
+        1.0
+    }
+}
+
+#[derive(Debug)]
+pub struct TentFilter {
+    radius: f64,
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> TentFilter {
+        TentFilter { radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0)
+    }
+}
+
+// Shifted down so that it reaches zero at the radius.
+#[derive(Debug)]
+pub struct GaussianFilter {
+    radius: f64,
+    alpha: f64,
+    edge: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, alpha: f64) -> GaussianFilter {
+        GaussianFilter {
+            radius,
+            alpha,
+            edge: (-alpha * radius * radius).exp(),
+        }
+    }
+
+    fn gaussian(&self, d: f64) -> f64 {
+        ((-self.alpha * d * d).exp() - self.edge).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.gaussian(x) * self.gaussian(y)
+    }
+}
+
+// Mitchell-Netravali cubic; b = c = 1/3 is the usual choice.
+#[derive(Debug)]
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f64, b: f64, c: f64) -> MitchellFilter {
+        MitchellFilter { radius, b, c }
+    }
+
+    fn mitchell(&self, x: f64) -> f64 {
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            ((-b - 6.0 * c) * x.powi(3) + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.mitchell(x) * self.mitchell(y)
+    }
+}
+
+// Four-term Blackman-Harris window stretched over the filter width.
+#[derive(Debug)]
+pub struct BlackmanHarrisFilter {
+    radius: f64,
+}
+
+impl BlackmanHarrisFilter {
+    pub fn new(radius: f64) -> BlackmanHarrisFilter {
+        BlackmanHarrisFilter { radius }
+    }
+
+    fn window(&self, x: f64) -> f64 {
+        if x.abs() > self.radius {
+            return 0.0;
+        }
+        let t = 2.0 * PI * (x + self.radius) / (2.0 * self.radius);
+        0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+    }
+}
+
+impl Filter for BlackmanHarrisFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.window(x) * self.window(y)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Sinc windowed by a sinc stretched to the radius.
+#[derive(Debug)]
+pub struct LanczosFilter {
+    radius: f64,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: f64) -> LanczosFilter {
+        LanczosFilter { radius }
+    }
+
+    fn lanczos(&self, x: f64) -> f64 {
+        if x.abs() > self.radius {
+            0.0
+        } else {
+            sinc(x) * sinc(x / self.radius)
+        }
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.lanczos(x) * self.lanczos(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters() -> Vec<Box<dyn Filter>> {
+        vec![
+            Box::new(BoxFilter::new(0.5)),
+            Box::new(TentFilter::new(1.0)),
+            Box::new(GaussianFilter::new(1.5, 2.0)),
+            Box::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)),
+            Box::new(BlackmanHarrisFilter::new(1.5)),
+            Box::new(LanczosFilter::new(3.0)),
+        ]
+    }
+
+    #[test]
+    fn filters_are_symmetric_with_positive_volume() {
+        for f in filters() {
+            let r = f.radius();
+            let n = 64;
+            let mut volume = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let x = r * (2.0 * (i as f64 + 0.5) / n as f64 - 1.0);
+                    let y = r * (2.0 * (j as f64 + 0.5) / n as f64 - 1.0);
+                    let w = f.evaluate(x, y);
+                    assert!((w - f.evaluate(-x, y)).abs() < 1e-12 && (w - f.evaluate(x, -y)).abs() < 1e-12);
+                    volume += w;
+                }
+            }
+            assert!(volume > 0.0);
+        }
+    }
+
+    #[test]
+    fn only_mitchell_and_lanczos_have_negative_lobes() {
+        for (k, f) in filters().iter().enumerate() {
+            let r = f.radius();
+            let lowest = (0..=100)
+                .map(|i| f.evaluate(r * i as f64 / 100.0, 0.0))
+                .fold(f64::INFINITY, f64::min);
+            assert_eq!(lowest < -1e-6, k == 3 || k == 5, "filter {}", k);
+        }
+    }
+}
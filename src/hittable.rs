@@ -3,17 +3,46 @@ use crate::material::Material;
 use crate::ray::{Point3, Ray};
 use crate::utils::degrees_to_radians;
 use crate::vec3::Vec3;
+use std::cell::Cell;
 use std::sync::Arc;
 
+thread_local! {
+    static NEXT_OBJECT_ID: Cell<u32> = const { Cell::new(1) };
+}
+
+// Primitives are numbered in the order the scene creates them, as materials
+// are, so an object keeps its id in every frame and under any BVH.
+pub fn new_object_id() -> u32 {
+    NEXT_OBJECT_ID.with(|n| {
+        let id = n.get();
+        n.set(id + 1);
+        id
+    })
+}
+
 pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
-    fn pdf_value(&self, _o: &Point3, _v: &Vec3) -> f64 {
+    fn pdf_value(&self, _o: &Point3, _v: &Vec3, _time: f64) -> f64 {
         0.0
     }
-    fn random(&self, _o: &Vec3) -> Vec3 {
+    fn random(&self, _o: &Vec3, _time: f64) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+    fn power(&self) -> f64 {
+        0.0
+    }
+}
+
+pub fn hit_alpha_tested(h: &dyn Hittable, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let mut t_min = t_min;
+    loop {
+        let rec = h.hit(r, t_min, t_max)?;
+        if rec.mat.alpha_test(&rec) {
+            return Some(rec);
+        }
+        t_min = rec.t + 0.0001;
+    }
 }
 
 pub struct HitRecord {
@@ -24,6 +53,12 @@ pub struct HitRecord {
     pub v: f64,
     pub front_face: bool,
     pub mat: Arc<dyn Material>,
+    // the primitive hit, from new_object_id
+    pub object_id: u32,
+    // light group of an emitter, 0 when it is in none
+    pub light_group: u32,
+    // scattering inside a participating medium rather than at a surface
+    pub volume: bool,
 }
 
 impl HitRecord {
@@ -36,6 +71,9 @@ impl HitRecord {
             v,
             front_face: false,
             mat,
+            object_id: 0,
+            light_group: 0,
+            volume: false,
         }
     }
 
@@ -73,17 +111,18 @@ impl Hittable for Translate {
         }
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
-        if let Some(out_box) = self.h.bounding_box(time0, time1) {
-            Some(Aabb::new(out_box.min + self.offset, out_box.max + self.offset))
-        } else {
-            None
-        }
+        self.h
+            .bounding_box(time0, time1)
+            .map(|out_box| Aabb::new(out_box.min + self.offset, out_box.max + self.offset))
+    }
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.h.pdf_value(&(*o - self.offset), v, time)
     }
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
-        self.h.pdf_value(o, v)
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.h.random(&(*o - self.offset), time)
     }
-    fn random(&self, o: &Vec3) -> Vec3 {
-        self.h.random(o)
+    fn power(&self) -> f64 {
+        self.h.power()
     }
 }
 
@@ -130,6 +169,22 @@ impl RotateY {
             bbox: Some(Aabb::new(min, max)),
         }
     }
+
+    fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v[0] - self.sin_theta * v[2],
+            v[1],
+            self.sin_theta * v[0] + self.cos_theta * v[2],
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v[0] + self.sin_theta * v[2],
+            v[1],
+            -self.sin_theta * v[0] + self.cos_theta * v[2],
+        )
+    }
 }
 
 impl Hittable for RotateY {
@@ -165,11 +220,14 @@ impl Hittable for RotateY {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         self.bbox
     }
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
-        self.h.pdf_value(o, v)
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.h.pdf_value(&self.to_local(o), &self.to_local(v), time)
     }
-    fn random(&self, o: &Vec3) -> Vec3 {
-        self.h.random(o)
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.to_world(&self.h.random(&self.to_local(o), time))
+    }
+    fn power(&self) -> f64 {
+        self.h.power()
     }
 }
 
@@ -195,10 +253,46 @@ impl Hittable for FlipFace {
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         self.h.bounding_box(time0, time1)
     }
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
-        self.h.pdf_value(o, v)
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.h.pdf_value(o, v, time)
+    }
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.h.random(o, time)
+    }
+    fn power(&self) -> f64 {
+        self.h.power()
+    }
+}
+
+// Tags what an emitter contributes with a light group, so that each group
+// can be written to its own pass.
+pub struct LightGroup {
+    h: Arc<dyn Hittable>,
+    group: u32,
+}
+
+impl LightGroup {
+    pub fn new(h: Arc<dyn Hittable>, group: u32) -> LightGroup {
+        LightGroup { h, group }
+    }
+}
+
+impl Hittable for LightGroup {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.h.hit(r, t_min, t_max)?;
+        rec.light_group = self.group;
+        Some(rec)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.h.bounding_box(time0, time1)
+    }
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.h.pdf_value(o, v, time)
+    }
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.h.random(o, time)
     }
-    fn random(&self, o: &Vec3) -> Vec3 {
-        self.h.random(o)
+    fn power(&self) -> f64 {
+        self.h.power()
     }
 }
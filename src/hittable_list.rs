@@ -1,4 +1,4 @@
-use crate::prelude::{random_int, Aabb, HitRecord, Hittable, Point3, Ray, Vec3};
+use crate::prelude::{hit_alpha_tested, random_int, Aabb, HitRecord, Hittable, Point3, Ray, Vec3};
 use std::sync::Arc;
 
 pub struct HittableList {
@@ -25,7 +25,7 @@ impl Hittable for HittableList {
         let mut closest_so_far = t_max;
 
         for object in self.objects.iter() {
-            if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+            if let Some(rec) = hit_alpha_tested(object.as_ref(), r, t_min, closest_so_far) {
                 closest_so_far = rec.t;
                 temp_rec = Some(rec);
             }
@@ -53,18 +53,22 @@ impl Hittable for HittableList {
         Some(output_box)
     }
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
         let weight = 1.0 / self.objects.len() as f64;
         let mut sum = 0.0;
 
         for object in &self.objects {
-            sum += weight * object.pdf_value(o, v);
+            sum += weight * object.pdf_value(o, v, time);
         }
 
         sum
     }
 
-    fn random(&self, o: &Vec3) -> Vec3 {
-        self.objects[random_int(0, self.objects.len() - 1)].random(o)
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.objects[random_int(0, self.objects.len() - 1)].random(o, time)
+    }
+
+    fn power(&self) -> f64 {
+        self.objects.iter().map(|o| o.power()).sum()
     }
 }
@@ -0,0 +1,215 @@
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::lpe::Event;
+use crate::material::{new_material_id, Material, ScatterRecord};
+use crate::ray::{Point3, Ray};
+use crate::utils::random_f64;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// representative wavelengths (nm) for the r, g and b channels
+pub const RGB_WAVELENGTHS: [f64; 3] = [650.0, 532.0, 450.0];
+
+// amplitude reflection coefficients (s, p) at an interface n1 -> n2
+fn fresnel_amplitudes(cos1: f64, cos2: f64, n1: f64, n2: f64) -> (f64, f64) {
+    let rs = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let rp = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    (rs, rp)
+}
+
+fn refracted_cos(cos_i: f64, eta_i: f64, eta_t: f64) -> Option<f64> {
+    let sin_t = eta_i / eta_t * (1.0 - cos_i * cos_i).max(0.0).sqrt();
+    if sin_t >= 1.0 {
+        None
+    } else {
+        Some((1.0 - sin_t * sin_t).sqrt())
+    }
+}
+
+pub fn fresnel_dielectric(cos_i: f64, eta_i: f64, eta_t: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    if let Some(cos_t) = refracted_cos(cos_i, eta_i, eta_t) {
+        let (rs, rp) = fresnel_amplitudes(cos_i, cos_t, eta_i, eta_t);
+        0.5 * (rs * rs + rp * rp)
+    } else {
+        1.0
+    }
+}
+
+// Airy reflectance of a film (ior n2, thickness in nm) between media n1 and n3
+pub fn thin_film_reflectance(cos_i: f64, n1: f64, n2: f64, n3: f64, thickness: f64, wavelength: f64) -> f64 {
+    let cos1 = cos_i.clamp(0.0, 1.0);
+    let cos2 = match refracted_cos(cos1, n1, n2) {
+        Some(c) => c,
+        None => return 1.0,
+    };
+    let (rs12, rp12) = fresnel_amplitudes(cos1, cos2, n1, n2);
+    let (rs23, rp23) = match refracted_cos(cos1, n1, n3) {
+        Some(cos3) => fresnel_amplitudes(cos2, cos3, n2, n3),
+        None => (1.0, 1.0),
+    };
+    let cos_delta = (4.0 * PI * n2 * thickness * cos2 / wavelength).cos();
+    let airy = |r12: f64, r23: f64| {
+        let cross = 2.0 * r12 * r23 * cos_delta;
+        (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+    };
+    0.5 * (airy(rs12, rs23) + airy(rp12, rp23))
+}
+
+fn thin_film_color(cos_i: f64, n1: f64, n2: f64, n3: f64, thickness: f64) -> Color {
+    let mut c = Color::new(0.0, 0.0, 0.0);
+    for i in 0..3 {
+        c[i] = thin_film_reflectance(cos_i, n1, n2, n3, thickness, RGB_WAVELENGTHS[i]);
+    }
+    c
+}
+
+fn average(c: Color) -> f64 {
+    (c.x() + c.y() + c.z()) / 3.0
+}
+
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ior: f64,
+    thickness: f64,
+    absorption: Color,
+    film: Option<(f64, f64)>,
+    id: u32,
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, ior: f64, thickness: f64, absorption: Color) -> Coated {
+        Coated {
+            base,
+            ior,
+            thickness,
+            absorption,
+            film: None,
+            id: new_material_id(),
+        }
+    }
+
+    // film_thickness is in nanometres; substrate_ior is the medium under the film
+    pub fn new_thin_film(base: Arc<dyn Material>, ior: f64, film_thickness: f64, substrate_ior: f64) -> Coated {
+        Coated {
+            base,
+            ior,
+            thickness: 0.0,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            film: Some((film_thickness, substrate_ior)),
+            id: new_material_id(),
+        }
+    }
+
+    fn reflectance(&self, cos_i: f64) -> Color {
+        if let Some((film_thickness, substrate_ior)) = self.film {
+            thin_film_color(cos_i, 1.0, self.ior, substrate_ior, film_thickness)
+        } else {
+            let f = fresnel_dielectric(cos_i, 1.0, self.ior);
+            Color::new(f, f, f)
+        }
+    }
+
+    fn transmittance(&self, cos_i: f64) -> Color {
+        let cos_t = refracted_cos(cos_i, 1.0, self.ior).unwrap_or(1.0);
+        let path = 2.0 * self.thickness / cos_t;
+        Color::new(
+            (-self.absorption.x() * path).exp(),
+            (-self.absorption.y() * path).exp(),
+            (-self.absorption.z() * path).exp(),
+        )
+    }
+}
+
+impl Material for Coated {
+    fn id(&self) -> u32 {
+        self.id
+    }
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if !rec.front_face {
+            return self.base.scatter(r_in, rec);
+        }
+        let unit_direction = r_in.dir.unit_vector();
+        let cos_i = (-unit_direction.dot(rec.normal)).min(1.0);
+        let r = self.reflectance(cos_i);
+        let p_reflect = average(r);
+
+        if random_f64() < p_reflect {
+            let reflected = unit_direction.reflect(rec.normal);
+            return Some(ScatterRecord::new(Ray::new(rec.p, reflected, r_in.time), r / p_reflect, None));
+        }
+        let mut srec = self.base.scatter(r_in, rec)?;
+        let t = Color::new(1.0, 1.0, 1.0) - r;
+        srec.attenuation = srec.attenuation * t * self.transmittance(cos_i) / (1.0 - p_reflect);
+        Some(srec)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let base = self.base.scattering_pdf(r_in, rec, scattered);
+        if !rec.front_face || self.film.is_some() {
+            return base;
+        }
+        // light leaving the base is attenuated again on its way out of the coating
+        let cos_o = scattered.dir.unit_vector().dot(rec.normal);
+        base * (1.0 - fresnel_dielectric(cos_o, 1.0, self.ior))
+    }
+    fn emitted(&self, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+        self.base.emitted(rec, u, v, p)
+    }
+    fn alpha_test(&self, rec: &HitRecord) -> bool {
+        self.base.alpha_test(rec)
+    }
+    fn average_emitted(&self) -> Color {
+        self.base.average_emitted()
+    }
+    fn event(&self, rec: &HitRecord, srec: Option<&ScatterRecord>) -> Event {
+        match srec {
+            // the coat reflects like a mirror, without a pdf
+            Some(s) if rec.front_face && s.pdf.is_none() => Event::Specular,
+            _ => self.base.event(rec, srec),
+        }
+    }
+}
+
+pub struct ThinFilm {
+    ior: f64,
+    thickness: f64,
+    id: u32,
+}
+
+impl ThinFilm {
+    // thickness is in nanometres
+    pub fn new(ior: f64, thickness: f64) -> ThinFilm {
+        ThinFilm {
+            ior,
+            thickness,
+            id: new_material_id(),
+        }
+    }
+}
+
+impl Material for ThinFilm {
+    fn id(&self) -> u32 {
+        self.id
+    }
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let unit_direction = r_in.dir.unit_vector();
+        let cos_i = (-unit_direction.dot(rec.normal)).min(1.0);
+        let r = thin_film_color(cos_i, 1.0, self.ior, 1.0, self.thickness);
+        let p_reflect = average(r);
+
+        if random_f64() < p_reflect {
+            let reflected = unit_direction.reflect(rec.normal);
+            Some(ScatterRecord::new(Ray::new(rec.p, reflected, r_in.time), r / p_reflect, None))
+        } else {
+            let t = Color::new(1.0, 1.0, 1.0) - r;
+            Some(ScatterRecord::new(
+                Ray::new(rec.p, unit_direction, r_in.time),
+                t / (1.0 - p_reflect),
+                None,
+            ))
+        }
+    }
+    fn event(&self, _rec: &HitRecord, _srec: Option<&ScatterRecord>) -> Event {
+        Event::Specular
+    }
+}
@@ -0,0 +1,366 @@
+use crate::camera::{Camera, CameraFrame, CameraSettings};
+use crate::ray::*;
+use crate::utils::*;
+use crate::vec3::*;
+
+// One surface of a lens prescription, or the aperture stop when `radius` is
+// 0. Lengths are in millimetres; `thickness` runs to the next surface
+// towards the film and `ior` is the index of the glass (or air) in between.
+#[derive(Clone, Copy)]
+pub struct LensElement {
+    pub radius: f64,
+    pub thickness: f64,
+    pub ior: f64,
+    pub aperture_radius: f64,
+}
+
+// Reads a prescription with one surface per line, front to back: radius,
+// thickness, index of refraction and aperture diameter. `#` starts a comment.
+pub fn load_lens(p: &std::path::Path) -> Vec<LensElement> {
+    let text = std::fs::read_to_string(p).unwrap();
+    text.lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let v: Vec<f64> = line.split_whitespace().map(|x| x.parse().unwrap()).collect();
+            assert!(v.len() == 4, "expected radius, thickness, ior and aperture in {}", line);
+            LensElement {
+                radius: v[0],
+                thickness: v[1],
+                // the stop is usually written with an index of 0
+                ior: if v[2] == 0.0 { 1.0 } else { v[2] },
+                aperture_radius: v[3] / 2.0,
+            }
+        })
+        .collect()
+}
+
+// Number of bands of film radius with their own exit pupil bounds.
+const PUPIL_BANDS: usize = 64;
+
+// Traces rays from the film through every element of a real lens, so
+// vignetting, distortion, field curvature and focus breathing come from the
+// glass itself (Kolb et al., as in pbrt). The lens is focused on `lookat` by
+// moving it away from the film, and stopped down to the f-number of the
+// camera settings. In lens space the film is the z = 0 plane and the scene
+// lies towards -z.
+pub struct RealisticCamera {
+    frame: CameraFrame,
+    elements: Vec<LensElement>,
+    film_width: f64,
+    film_height: f64,
+    units_per_mm: f64,
+    // boxes on the plane of the rear element that hold every ray reaching
+    // the scene from film points on the +x axis, band by band
+    pupil_bounds: Vec<[f64; 4]>,
+    max_pupil_area: f64,
+}
+
+impl RealisticCamera {
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        elements: Vec<LensElement>,
+        settings: &CameraSettings,
+        aspect_ratio: f64,
+        units_per_mm: f64,
+    ) -> RealisticCamera {
+        let film_width = settings.sensor_width.min(settings.sensor_height * aspect_ratio);
+        let mut camera = RealisticCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup, 0.0, 1.0),
+            elements,
+            film_width,
+            film_height: film_width / aspect_ratio,
+            units_per_mm,
+            pupil_bounds: Vec::new(),
+            max_pupil_area: 0.0,
+        };
+        camera.stop_down(settings.f_stop);
+        let focus_dist = (lookat - lookfrom).length() / units_per_mm;
+        let delta = camera.focus_shift(focus_dist);
+        camera.elements.last_mut().unwrap().thickness += delta;
+        camera.pupil_bounds = (0..PUPIL_BANDS).map(|i| camera.pupil_bound(i)).collect();
+        camera.max_pupil_area = camera.pupil_bounds.iter().map(area).fold(0.0, f64::max);
+        camera
+    }
+
+    fn rear_z(&self) -> f64 {
+        -self.elements.last().unwrap().thickness
+    }
+
+    fn front_z(&self) -> f64 {
+        -self.elements.iter().map(|e| e.thickness).sum::<f64>()
+    }
+
+    fn half_diagonal(&self) -> f64 {
+        (self.film_width * self.film_width + self.film_height * self.film_height).sqrt() / 2.0
+    }
+
+    // Follows a ray leaving the film out of the front of the lens.
+    fn trace_from_film(&self, mut o: Vec3, mut d: Vec3) -> Option<(Vec3, Vec3)> {
+        let mut z = 0.0;
+        for (i, e) in self.elements.iter().enumerate().rev() {
+            z -= e.thickness;
+            let outside = if i == 0 { 1.0 } else { self.elements[i - 1].ior };
+            (o, d) = self.cross(e, z, o, d, e.ior, outside)?;
+        }
+        Some((o, d))
+    }
+
+    // Follows a ray from the scene out of the back of the lens, or only
+    // through its first `count` surfaces.
+    fn trace_from_scene(&self, o: Vec3, d: Vec3) -> Option<(Vec3, Vec3)> {
+        self.trace_from_scene_through(self.elements.len(), o, d)
+    }
+
+    fn trace_from_scene_through(&self, count: usize, mut o: Vec3, mut d: Vec3) -> Option<(Vec3, Vec3)> {
+        let mut z = self.front_z();
+        for (i, e) in self.elements.iter().enumerate().take(count) {
+            let outside = if i == 0 { 1.0 } else { self.elements[i - 1].ior };
+            (o, d) = self.cross(e, z, o, d, outside, e.ior)?;
+            z += e.thickness;
+        }
+        Some((o, d))
+    }
+
+    // Intersects the surface at `z` on the axis and refracts from index
+    // `eta_i` into `eta_t`, or None if the ray misses or is reflected.
+    fn cross(&self, e: &LensElement, z: f64, o: Vec3, d: Vec3, eta_i: f64, eta_t: f64) -> Option<(Vec3, Vec3)> {
+        if e.radius == 0.0 {
+            let t = (z - o.z()) / d.z();
+            let p = o + t * d;
+            if t < 0.0 || p.x() * p.x() + p.y() * p.y() > e.aperture_radius * e.aperture_radius {
+                return None;
+            }
+            return Some((p, d));
+        }
+        let center = Vec3::new(0.0, 0.0, z + e.radius);
+        let oc = o - center;
+        let a = d.length_squared();
+        let half_b = oc.dot(d);
+        let c = oc.length_squared() - e.radius * e.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let (t0, t1) = ((-half_b - discriminant.sqrt()) / a, (-half_b + discriminant.sqrt()) / a);
+        // the surface is the cap of the sphere nearest its vertex at `z`
+        let t = if (d.z() > 0.0) != (e.radius < 0.0) { t0 } else { t1 };
+        let p = o + t * d;
+        if t < 0.0 || p.x() * p.x() + p.y() * p.y() > e.aperture_radius * e.aperture_radius {
+            return None;
+        }
+        let mut n = (p - center).unit_vector();
+        if n.dot(d) > 0.0 {
+            n = -n;
+        }
+        let d = refract(d.unit_vector(), n, eta_i / eta_t)?;
+        Some((p, d))
+    }
+
+    // A ray entering parallel to the axis, just off it.
+    fn paraxial_ray(&self) -> (Vec3, Vec3) {
+        let x = 0.001 * self.half_diagonal();
+        (Vec3::new(x, 0.0, self.front_z() - 1.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    // Image side focal point and principal plane of the thick lens
+    // approximation.
+    fn cardinal_points(&self) -> (f64, f64) {
+        let (start, dir) = self.paraxial_ray();
+        let (o, d) = self.trace_from_scene(start, dir).expect("lens does not pass paraxial rays");
+        let focal_z = o.z() - o.x() / d.x() * d.z();
+        let principal_z = o.z() + (start.x() - o.x()) / d.x() * d.z();
+        (focal_z, principal_z)
+    }
+
+    fn focal_length(&self) -> f64 {
+        let (focal_z, principal_z) = self.cardinal_points();
+        focal_z - principal_z
+    }
+
+    // Sizes the stop so the entrance pupil is focal length / f_stop across,
+    // as pbrt's aperture diameter does directly. It is never opened wider
+    // than the prescription allows.
+    fn stop_down(&mut self, f_stop: f64) {
+        let Some(stop) = self.elements.iter().position(|e| e.radius == 0.0) else {
+            return;
+        };
+        // the front elements scale ray heights on their way to the stop
+        let (start, dir) = self.paraxial_ray();
+        let (o, _) = self
+            .trace_from_scene_through(stop + 1, start, dir)
+            .expect("lens does not pass paraxial rays");
+        let pupil_radius = self.focal_length() / f_stop / 2.0;
+        let radius = (pupil_radius * o.x() / start.x()).abs();
+        let e = &mut self.elements[stop];
+        e.aperture_radius = e.aperture_radius.min(radius);
+    }
+
+    // How far to move the lens from the film to focus at `focus_dist` mm,
+    // from the cardinal points of its thick lens approximation.
+    fn focus_shift(&self, focus_dist: f64) -> f64 {
+        let x = 0.001 * self.half_diagonal();
+        let (focal_z, principal_z) = self.cardinal_points();
+        let f = focal_z - principal_z;
+        let (o, d) = self
+            .trace_from_film(Vec3::new(x, 0.0, self.rear_z() + 1.0), Vec3::new(0.0, 0.0, -1.0))
+            .expect("lens does not pass paraxial rays");
+        let object_principal_z = o.z() + (x - o.x()) / d.x() * d.z();
+        // object and image distances add up to the span between the object
+        // and the film, less the gap between the principal planes
+        let span = object_principal_z + focus_dist - principal_z;
+        let image_dist = (span - (span * span - 4.0 * f * span).sqrt()) / 2.0;
+        image_dist + principal_z
+    }
+
+    fn pupil_bound(&self, band: usize) -> [f64; 4] {
+        const FILM_POINTS: usize = 8;
+        const GRID: usize = 64;
+        let rear = self.elements.last().unwrap().aperture_radius;
+        let rear_z = self.rear_z();
+        let cell = 2.0 * rear / GRID as f64;
+        let mut b = [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY];
+        for k in 0..FILM_POINTS {
+            let r = (band as f64 + k as f64 / (FILM_POINTS - 1) as f64) / PUPIL_BANDS as f64 * self.half_diagonal();
+            for j in 0..GRID {
+                for i in 0..GRID {
+                    let px = -rear + (i as f64 + 0.5) * cell;
+                    let py = -rear + (j as f64 + 0.5) * cell;
+                    let film = Vec3::new(r, 0.0, 0.0);
+                    if self.trace_from_film(film, Vec3::new(px, py, rear_z) - film).is_some() {
+                        b = [b[0].min(px), b[1].min(py), b[2].max(px), b[3].max(py)];
+                    }
+                }
+            }
+        }
+        if b[0] > b[2] {
+            return [0.0; 4];
+        }
+        // grow by a cell for the rays between the grid points
+        [b[0] - cell, b[1] - cell, b[2] + cell, b[3] + cell]
+    }
+}
+
+impl Camera for RealisticCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        // the lens turns the image over
+        let fx = -(s - 0.5) * self.film_width;
+        let fy = -(t - 0.5) * self.film_height;
+        let r = (fx * fx + fy * fy).sqrt();
+        let band = ((r / self.half_diagonal() * PUPIL_BANDS as f64) as usize).min(PUPIL_BANDS - 1);
+        let b = &self.pupil_bounds[band];
+        let px = b[0] + random_f64() * (b[2] - b[0]);
+        let py = b[1] + random_f64() * (b[3] - b[1]);
+        // keep the density on the pupil the same in every band, so that
+        // blocked rays darken the image by the right amount
+        if random_f64() * self.max_pupil_area >= area(b) {
+            return None;
+        }
+        let (sin, cos) = if r > 0.0 { (fy / r, fx / r) } else { (0.0, 1.0) };
+        let film = Vec3::new(fx, fy, 0.0);
+        let d = Vec3::new(px * cos - py * sin, px * sin + py * cos, self.rear_z()) - film;
+        // irradiance on the film falls off with the fourth power of the cosine
+        let cos_theta = d.z().abs() / d.length();
+        if random_f64() >= cos_theta.powi(4) {
+            return None;
+        }
+        let (o, d) = self.trace_from_film(film, d)?;
+        let origin = self.frame.origin + self.units_per_mm * self.frame.to_world(o.x(), o.y(), -o.z());
+        self.frame.ray(origin, self.frame.to_world(d.x(), d.y(), -d.z()))
+    }
+}
+
+fn area(b: &[f64; 4]) -> f64 {
+    (b[2] - b[0]) * (b[3] - b[1])
+}
+
+// Refraction of the unit direction `d` through a surface with normal `n`
+// facing it, or None on total internal reflection.
+fn refract(d: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = -d.dot(n);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(eta * d + (eta * cos_i - cos_t) * n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(f_stop: f64) -> CameraSettings {
+        CameraSettings::new(50.0, f_stop, 36.0, 24.0, 1.0 / 60.0, 100.0)
+    }
+
+    // stopped down but without the slow pupil bounds, which only rays need
+    fn dgauss(f_stop: f64) -> RealisticCamera {
+        let mut camera = RealisticCamera {
+            frame: CameraFrame::new(
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(0.0, 0.0, -5.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                0.0,
+                1.0,
+            ),
+            elements: load_lens(std::path::Path::new("lenses/dgauss.50mm.lens")),
+            film_width: 36.0,
+            film_height: 24.0,
+            units_per_mm: 0.001,
+            pupil_bounds: Vec::new(),
+            max_pupil_area: 0.0,
+        };
+        camera.stop_down(f_stop);
+        camera
+    }
+
+    // radius of the widest beam parallel to the axis that gets through
+    fn entrance_pupil_radius(camera: &RealisticCamera) -> f64 {
+        let (mut lo, mut hi) = (0.0, camera.elements[0].aperture_radius);
+        for _ in 0..40 {
+            let h = (lo + hi) / 2.0;
+            let start = Vec3::new(h, 0.0, camera.front_z() - 1.0);
+            if camera.trace_from_scene(start, Vec3::new(0.0, 0.0, 1.0)).is_some() {
+                lo = h;
+            } else {
+                hi = h;
+            }
+        }
+        lo
+    }
+
+    #[test]
+    fn double_gauss_has_a_50mm_focal_length() {
+        assert!((dgauss(2.0).focal_length() - 50.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn f_stop_sets_the_entrance_pupil() {
+        for f_stop in [4.0, 8.0] {
+            let camera = dgauss(f_stop);
+            let expected = camera.focal_length() / f_stop / 2.0;
+            assert!((entrance_pupil_radius(&camera) / expected - 1.0).abs() < 0.01);
+        }
+        // the prescription limits how far the lens opens
+        let wide_open = dgauss(1.0);
+        assert!(entrance_pupil_radius(&wide_open) < wide_open.focal_length() / 2.0 * 0.9);
+    }
+
+    #[test]
+    fn rays_leave_towards_lookat() {
+        let lens = load_lens(std::path::Path::new("lenses/dgauss.50mm.lens"));
+        let (from, at, up) = (Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 1.0, 0.0));
+        let camera = RealisticCamera::new(from, at, up, lens, &settings(8.0), 1.5, 0.001);
+        let mut forward = 0;
+        for _ in 0..200 {
+            if let Some(r) = camera.get_ray(0.5, 0.5) {
+                assert!(r.dir.unit_vector().z() < -0.99);
+                forward += 1;
+            }
+        }
+        assert!(forward > 0);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::color::Color;
+use crate::ray::Point3;
+use crate::utils::degrees_to_radians;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Lights that are never hit by rays (points, spots, directions) and are
+// sampled explicitly from the shading point instead.
+pub trait Light: Send + Sync {
+    fn sample(&self, p: Point3) -> Option<LightSample>;
+    fn group(&self) -> u32 {
+        0
+    }
+}
+
+// Puts a light in a light group, like `LightGroup` does for emitters.
+pub struct GroupedLight {
+    light: Arc<dyn Light>,
+    group: u32,
+}
+
+impl GroupedLight {
+    pub fn new(light: Arc<dyn Light>, group: u32) -> GroupedLight {
+        GroupedLight { light, group }
+    }
+}
+
+impl Light for GroupedLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        self.light.sample(p)
+    }
+    fn group(&self) -> u32 {
+        self.group
+    }
+}
+
+pub struct LightSample {
+    pub dir: Vec3,
+    pub distance: f64,
+    pub radiance: Color,
+}
+
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+    range: Option<f64>,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+            range: None,
+        }
+    }
+
+    // inverse square falloff windowed smoothly to zero at `range`
+    pub fn new_with_range(position: Point3, intensity: Color, range: f64) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+            range: Some(range),
+        }
+    }
+}
+
+fn falloff(distance_squared: f64, range: Option<f64>) -> f64 {
+    let window = if let Some(r) = range {
+        let x = distance_squared * distance_squared / (r * r * r * r);
+        (1.0 - x).max(0.0).powi(2)
+    } else {
+        1.0
+    };
+    window / distance_squared
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        let attenuation = falloff(distance_squared, self.range);
+        if attenuation <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        Some(LightSample {
+            dir: to_light / distance,
+            distance,
+            radiance: attenuation * self.intensity,
+        })
+    }
+}
+
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    // angles are the full-intensity cone and the cutoff cone, in degrees from the axis
+    pub fn new(position: Point3, direction: Vec3, intensity: Color, inner_angle: f64, outer_angle: f64) -> SpotLight {
+        SpotLight {
+            position,
+            direction: direction.unit_vector(),
+            intensity,
+            cos_inner: degrees_to_radians(inner_angle).cos(),
+            cos_outer: degrees_to_radians(outer_angle).cos(),
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let dir = to_light / distance;
+        let cos_theta = (-dir).dot(self.direction);
+        if cos_theta <= self.cos_outer {
+            return None;
+        }
+        let edge = if cos_theta >= self.cos_inner {
+            1.0
+        } else {
+            let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        };
+        Some(LightSample {
+            dir,
+            distance,
+            radiance: edge / (distance * distance) * self.intensity,
+        })
+    }
+}
+
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    // direction is the way the light travels, e.g. (0, -1, 0) for a sun overhead
+    pub fn new(direction: Vec3, irradiance: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.unit_vector(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Point3) -> Option<LightSample> {
+        Some(LightSample {
+            dir: -self.direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+        })
+    }
+}
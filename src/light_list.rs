@@ -0,0 +1,271 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::ray::{Point3, Ray};
+use crate::utils::random_f64;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// lists at least this long get a light tree instead of a flat power cdf
+const LIGHT_TREE_THRESHOLD: usize = 16;
+
+enum LightNode {
+    Leaf {
+        light: usize,
+        power: f64,
+        bbox: Option<Aabb>,
+    },
+    Interior {
+        left: usize,
+        right: usize,
+        power: f64,
+        bbox: Option<Aabb>,
+    },
+}
+
+impl LightNode {
+    fn power(&self) -> f64 {
+        match self {
+            LightNode::Leaf { power, .. } | LightNode::Interior { power, .. } => *power,
+        }
+    }
+
+    fn bbox(&self) -> Option<Aabb> {
+        match self {
+            LightNode::Leaf { bbox, .. } | LightNode::Interior { bbox, .. } => *bbox,
+        }
+    }
+
+    // power over squared distance to the cluster, clamped inside it;
+    // clusters without bounds (environment lights) are distance independent
+    fn importance(&self, p: &Point3) -> f64 {
+        if let Some(b) = self.bbox() {
+            let center = 0.5 * (b.min + b.max);
+            let radius_squared = 0.25 * (b.max - b.min).length_squared();
+            let distance_squared = (center - *p).length_squared();
+            self.power() / distance_squared.max(radius_squared).max(1e-8)
+        } else {
+            self.power()
+        }
+    }
+}
+
+// Picks lights in proportion to their emitted power, or for long lists by
+// their estimated contribution at the shading point through a light tree.
+// Lights that emit nothing themselves (e.g. a glass sphere sampled for its
+// caustics) are given the average power of the others. The tree bounds the
+// lights over [time0, time1], the times the scene's rays are traced at.
+pub struct LightList {
+    pub objects: Vec<Arc<dyn Hittable>>,
+    powers: Vec<f64>,
+    cdf: Vec<f64>,
+    nodes: Vec<LightNode>,
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl LightList {
+    pub fn new(list: HittableList, time0: f64, time1: f64) -> LightList {
+        let powers = list.objects.iter().map(|o| o.power()).collect();
+        LightList::new_with_powers(list.objects, powers, time0, time1)
+    }
+
+    pub fn new_with_powers(objects: Vec<Arc<dyn Hittable>>, mut powers: Vec<f64>, time0: f64, time1: f64) -> LightList {
+        let emitting: Vec<f64> = powers.iter().cloned().filter(|&p| p > 0.0).collect();
+        let fallback = if emitting.is_empty() {
+            1.0
+        } else {
+            emitting.iter().sum::<f64>() / emitting.len() as f64
+        };
+        for p in powers.iter_mut() {
+            if *p <= 0.0 {
+                *p = fallback;
+            }
+        }
+
+        let mut cdf = vec![0.0; powers.len() + 1];
+        for i in 0..powers.len() {
+            cdf[i + 1] = cdf[i] + powers[i];
+        }
+
+        let mut l = LightList {
+            objects,
+            powers,
+            cdf,
+            nodes: Vec::new(),
+            time0,
+            time1,
+        };
+        if l.objects.len() >= LIGHT_TREE_THRESHOLD {
+            let boxes: Vec<Option<Aabb>> = l.objects.iter().map(|o| o.bounding_box(time0, time1)).collect();
+            l.build_tree((0..l.objects.len()).collect(), &boxes);
+        }
+        l
+    }
+
+    fn build_tree(&mut self, mut indices: Vec<usize>, boxes: &[Option<Aabb>]) -> usize {
+        if indices.len() == 1 {
+            let i = indices[0];
+            self.nodes.push(LightNode::Leaf {
+                light: i,
+                power: self.powers[i],
+                bbox: boxes[i],
+            });
+            return self.nodes.len() - 1;
+        }
+
+        // split at the median centroid along the widest axis; unbounded lights sort first
+        let centroid = |i: usize| boxes[i].map(|b| 0.5 * (b.min + b.max));
+        let mut lo = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut hi = -lo;
+        for &i in &indices {
+            if let Some(c) = centroid(i) {
+                for a in 0..3 {
+                    lo[a] = lo[a].min(c[a]);
+                    hi[a] = hi[a].max(c[a]);
+                }
+            }
+        }
+        let extent = hi - lo;
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+            0
+        } else if extent.y() >= extent.z() {
+            1
+        } else {
+            2
+        };
+        indices.sort_by(|&a, &b| {
+            let ka = centroid(a).map_or(-f64::INFINITY, |c| c[axis]);
+            let kb = centroid(b).map_or(-f64::INFINITY, |c| c[axis]);
+            ka.partial_cmp(&kb).unwrap()
+        });
+
+        let right_indices = indices.split_off(indices.len() / 2);
+        let left = self.build_tree(indices, boxes);
+        let right = self.build_tree(right_indices, boxes);
+        let bbox = match (self.nodes[left].bbox(), self.nodes[right].bbox()) {
+            (Some(a), Some(b)) => Some(Aabb::surrounding_box(a, b)),
+            _ => None,
+        };
+        self.nodes.push(LightNode::Interior {
+            left,
+            right,
+            power: self.nodes[left].power() + self.nodes[right].power(),
+            bbox,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn left_probability(&self, left: usize, right: usize, o: &Point3) -> f64 {
+        let il = self.nodes[left].importance(o);
+        let ir = self.nodes[right].importance(o);
+        if il + ir <= 0.0 {
+            0.5
+        } else {
+            il / (il + ir)
+        }
+    }
+
+    fn tree_pdf_value(&self, node: usize, prob: f64, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        // the boxes say nothing about where moving lights are at other times
+        let bounded = (self.time0..=self.time1).contains(&time);
+        if let Some(b) = self.nodes[node].bbox().filter(|_| bounded) {
+            if !b.hit(&Ray::new(*o, *v, time), 0.001, f64::INFINITY) {
+                return 0.0;
+            }
+        }
+        match self.nodes[node] {
+            LightNode::Leaf { light, .. } => prob * self.objects[light].pdf_value(o, v, time),
+            LightNode::Interior { left, right, .. } => {
+                let pl = self.left_probability(left, right, o);
+                self.tree_pdf_value(left, prob * pl, o, v, time) + self.tree_pdf_value(right, prob * (1.0 - pl), o, v, time)
+            }
+        }
+    }
+}
+
+impl Hittable for LightList {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut temp_rec: Option<HitRecord> = None;
+        let mut closest_so_far = t_max;
+
+        for object in self.objects.iter() {
+            if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                temp_rec = Some(rec);
+            }
+        }
+        temp_rec
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let mut output_box: Option<Aabb> = None;
+        for object in self.objects.iter() {
+            let b = object.bounding_box(time0, time1)?;
+            output_box = Some(output_box.map_or(b, |o| Aabb::surrounding_box(o, b)));
+        }
+        output_box
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        if let Some(root) = self.nodes.len().checked_sub(1) {
+            return self.tree_pdf_value(root, 1.0, o, v, time);
+        }
+        let total = self.cdf[self.cdf.len() - 1];
+        let mut sum = 0.0;
+        for (object, power) in self.objects.iter().zip(&self.powers) {
+            sum += power / total * object.pdf_value(o, v, time);
+        }
+        sum
+    }
+
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        if let Some(root) = self.nodes.len().checked_sub(1) {
+            let mut node = root;
+            loop {
+                match self.nodes[node] {
+                    LightNode::Leaf { light, .. } => return self.objects[light].random(o, time),
+                    LightNode::Interior { left, right, .. } => {
+                        node = if random_f64() < self.left_probability(left, right, o) {
+                            left
+                        } else {
+                            right
+                        };
+                    }
+                }
+            }
+        }
+        let target = random_f64() * self.cdf[self.cdf.len() - 1];
+        let i = self.cdf.partition_point(|&c| c <= target).clamp(1, self.objects.len()) - 1;
+        self.objects[i].random(o, time)
+    }
+
+    fn power(&self) -> f64 {
+        self.powers.iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::DiffuseLight;
+    use crate::sphere::MovingSphere;
+
+    #[test]
+    fn tree_finds_moving_lights_late_in_a_sequence() {
+        let light = Arc::new(DiffuseLight::new_color(Color::new(4.0, 4.0, 4.0)));
+        let mut list = HittableList::new();
+        for i in 0..LIGHT_TREE_THRESHOLD {
+            let y = i as f64;
+            let (start, end) = (Point3::new(0.0, y, -10.0), Point3::new(20.0, y, -10.0));
+            list.add(Arc::new(MovingSphere::new(start, end, 0.0, 2.0, 0.5, light.clone())));
+        }
+        let lights = LightList::new(list, 0.0, 2.0);
+        assert!(!lights.nodes.is_empty());
+        // at 1.8 s every light is well outside where they were over [0, 1]
+        let o = Point3::new(0.0, 0.0, 0.0);
+        let towards = Point3::new(18.0, 3.0, -10.0) - o;
+        assert!(lights.pdf_value(&o, &towards, 1.8) > 0.0);
+    }
+}
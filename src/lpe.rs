@@ -0,0 +1,164 @@
+// Vertex types along a light path, from the camera to the light.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    Camera,
+    Diffuse,
+    Specular,
+    Volume,
+    Light,
+}
+
+impl Event {
+    fn symbol(&self) -> char {
+        match self {
+            Event::Camera => 'C',
+            Event::Diffuse => 'D',
+            Event::Specular => 'S',
+            Event::Volume => 'V',
+            Event::Light => 'L',
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Repeat {
+    Once,
+    Optional,
+    AnyNumber,
+}
+
+// A light path expression such as "C D+ L" or "C [DS]* S L": the symbols
+// C, D, S, V and L, `.` for any event, `[..]` for a choice, and the `?`,
+// `*` and `+` repeats. Whitespace is ignored; anything else, or a `[` left
+// open, is rejected when the expression is parsed.
+//
+// Paths are matched one event at a time. A state is the set of terms the
+// path so far could be about to match, as bits of a u64, so following a
+// path needs no memory of the events already taken.
+pub struct LightPathExpression {
+    pub source: String,
+    terms: Vec<(Vec<char>, Repeat)>,
+}
+
+impl LightPathExpression {
+    pub fn new(source: &str) -> LightPathExpression {
+        let mut terms: Vec<(Vec<char>, Repeat)> = Vec::new();
+        let mut chars = source.chars().filter(|c| !c.is_whitespace()).peekable();
+        while let Some(c) = chars.next() {
+            let set = match c {
+                'C' | 'D' | 'S' | 'V' | 'L' => vec![c],
+                '.' => vec!['C', 'D', 'S', 'V', 'L'],
+                '[' => {
+                    let mut set = Vec::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) if "CDSVL".contains(c) => set.push(c),
+                            Some(c) => panic!("bad event '{}' in light path expression {}", c, source),
+                            None => panic!("unclosed '[' in light path expression {}", source),
+                        }
+                    }
+                    set
+                }
+                _ => panic!("unexpected '{}' in light path expression {}", c, source),
+            };
+            match chars.next_if(|c| "?*+".contains(*c)) {
+                Some('?') => terms.push((set, Repeat::Optional)),
+                Some('*') => terms.push((set, Repeat::AnyNumber)),
+                // X+ is X X*
+                Some(_) => {
+                    terms.push((set.clone(), Repeat::Once));
+                    terms.push((set, Repeat::AnyNumber));
+                }
+                None => terms.push((set, Repeat::Once)),
+            }
+        }
+        assert!(terms.len() < 64, "light path expression {} is too long", source);
+        LightPathExpression {
+            source: source.to_string(),
+            terms,
+        }
+    }
+
+    // The state of the empty path.
+    pub fn start(&self) -> u64 {
+        self.skip_optional(1)
+    }
+
+    // The state after one more event; zero once nothing can match.
+    pub fn step(&self, state: u64, event: Event) -> u64 {
+        let symbol = event.symbol();
+        let mut next = 0;
+        for (i, (set, repeat)) in self.terms.iter().enumerate() {
+            if state & (1 << i) != 0 && set.contains(&symbol) {
+                next |= match repeat {
+                    Repeat::AnyNumber => 1 << i,
+                    _ => 1 << (i + 1),
+                };
+            }
+        }
+        self.skip_optional(next)
+    }
+
+    // Whether a path in this state matches the whole expression.
+    pub fn accepts(&self, state: u64) -> bool {
+        state & (1 << self.terms.len()) != 0
+    }
+
+    // adds the terms reachable by matching `?` and `*` terms zero times
+    fn skip_optional(&self, mut state: u64) -> u64 {
+        for (i, (_, repeat)) in self.terms.iter().enumerate() {
+            if state & (1 << i) != 0 && *repeat != Repeat::Once {
+                state |= 1 << (i + 1);
+            }
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Event::*;
+
+    fn matches(source: &str, path: &[Event]) -> bool {
+        let lpe = LightPathExpression::new(source);
+        lpe.accepts(path.iter().fold(lpe.start(), |s, e| lpe.step(s, *e)))
+    }
+
+    #[test]
+    fn repeats() {
+        assert!(matches("C D+ L", &[Camera, Diffuse, Light]));
+        assert!(matches("C D+ L", &[Camera, Diffuse, Diffuse, Diffuse, Light]));
+        assert!(!matches("C D+ L", &[Camera, Light]));
+        assert!(matches("C D* L", &[Camera, Light]));
+        assert!(matches("C S? D L", &[Camera, Diffuse, Light]));
+        assert!(matches("C S? D L", &[Camera, Specular, Diffuse, Light]));
+        assert!(!matches("C S? D L", &[Camera, Specular, Specular, Diffuse, Light]));
+    }
+
+    #[test]
+    fn choices_and_wildcards() {
+        // caustics: diffuse seen through any specular chain ending in S
+        assert!(matches("C [DS]* S L", &[Camera, Diffuse, Specular, Specular, Light]));
+        assert!(!matches("C [DS]* S L", &[Camera, Specular, Diffuse, Light]));
+        assert!(matches("C . . L", &[Camera, Volume, Specular, Light]));
+        assert!(!matches("C . . L", &[Camera, Volume, Light]));
+        // a repeat followed by the same symbol has to leave one for it
+        assert!(matches("C D* D L", &[Camera, Diffuse, Light]));
+    }
+
+    #[test]
+    fn dead_paths_stay_dead() {
+        let lpe = LightPathExpression::new("C D L");
+        let state = lpe.step(lpe.step(lpe.start(), Camera), Specular);
+        assert_eq!(state, 0);
+        assert_eq!(lpe.step(state, Diffuse), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "unclosed '['")]
+    fn unclosed_choices_are_rejected() {
+        LightPathExpression::new("C [DS L");
+    }
+}
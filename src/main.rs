@@ -3,86 +3,230 @@ extern crate rand;
 
 mod aabb;
 mod aarect;
+mod adaptive;
+mod animation;
+mod aov;
 mod bvh;
 mod camera;
 mod color;
 mod constant_medium;
+mod denoise;
+mod environment;
+mod film;
+mod filter;
 mod hittable;
 mod hittable_list;
+mod layered;
+mod lens;
+mod light;
+mod light_list;
+mod lpe;
 mod material;
 mod onb;
 mod pdf;
 mod perlin;
 mod ray;
 mod rectprism;
+mod sampler;
+mod sky;
+mod spectrum;
 mod sphere;
 mod texture;
+mod tiles;
+mod tonemap;
 mod utils;
 mod vec3;
 
 mod prelude {
     pub use crate::aabb::*;
     pub use crate::aarect::*;
+    pub use crate::adaptive::*;
+    pub use crate::animation::*;
+    pub use crate::aov::*;
     pub use crate::bvh::*;
     pub use crate::camera::*;
     pub use crate::color::*;
     pub use crate::constant_medium::*;
+    pub use crate::denoise::*;
+    pub use crate::environment::*;
+    pub use crate::film::*;
+    pub use crate::filter::*;
     pub use crate::hittable::*;
     pub use crate::hittable_list::*;
+    pub use crate::layered::*;
+    pub use crate::lens::*;
+    pub use crate::light::*;
+    pub use crate::light_list::*;
+    pub use crate::lpe::*;
     pub use crate::material::*;
     pub use crate::onb::*;
     pub use crate::pdf::*;
     pub use crate::perlin::*;
     pub use crate::ray::*;
     pub use crate::rectprism::*;
+    pub use crate::sampler::*;
+    pub use crate::sky::*;
+    pub use crate::spectrum::*;
     pub use crate::sphere::*;
     pub use crate::texture::*;
+    pub use crate::tiles::*;
+    pub use crate::tonemap::*;
     pub use crate::utils::*;
     pub use crate::vec3::*;
 }
 
 use crate::prelude::*;
-use rand::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+#[derive(Clone)]
 struct ImageData {
     height: u32,
     width: u32,
     samples_per_pixel: usize,
     max_depth: usize,
-    camera: Camera,
-    background: Color,
+    spectral: bool,
+    sampler: SamplerType,
+    seed: u64,
+    adaptive: Option<AdaptiveSampling>,
+    aovs: Vec<Aov>,
+    // number of light groups, counting the default group 0
+    light_groups: usize,
+    lpes: Arc<Vec<LightPathExpression>>,
+    camera: Arc<dyn Camera>,
+    background: Arc<dyn Environment>,
 }
 
 struct World {
     objects: HittableList,
-    lights: Arc<dyn Hittable>,
+    lights: Arc<LightList>,
+    delta_lights: Vec<Arc<dyn Light>>,
+    // names of light groups 1 and up; group 0 is everything else
+    light_groups: Vec<String>,
 }
 
-fn ray_color(r: &Ray, background: Color, world: &World, depth: usize) -> Color {
+impl World {
+    fn with_environment(self, env: Arc<dyn Environment>) -> World {
+        if !env.is_light() {
+            return self;
+        }
+        let mut lights = HittableList::new();
+        lights.objects = self.lights.objects.clone();
+        let (time0, time1) = (self.lights.time0, self.lights.time1);
+        let radius = self
+            .objects
+            .bounding_box(time0, time1)
+            .map_or(1.0, |b| 0.5 * (b.max - b.min).length());
+        lights.add(Arc::new(EnvironmentLight::new(env, radius)));
+        World {
+            lights: Arc::new(LightList::new(lights, time0, time1)),
+            ..self
+        }
+    }
+}
+
+// Light from each delta light that reaches the hit point, with its group.
+fn direct_lighting(r: &Ray, rec: &HitRecord, world: &World) -> Vec<(u32, Color)> {
+    let mut direct = Vec::new();
+    for light in &world.delta_lights {
+        if let Some(ls) = light.sample(rec.p) {
+            let shadow_ray = Ray::new(rec.p, ls.dir, r.time);
+            if world.objects.hit(&shadow_ray, 0.00001, ls.distance).is_none() {
+                direct.push((
+                    light.group(),
+                    rec.mat.scattering_pdf(r, rec, &shadow_ray) * to_spectral(ls.radiance, r),
+                ));
+            }
+        }
+    }
+    direct
+}
+
+// When `aov` is given it receives what the ray hits first, with the radiance
+// split into emission, light arriving in one bounce, and the rest, and by
+// light group and light path expression.
+fn ray_color(r: &Ray, background: &dyn Environment, world: &World, depth: usize, aov: Option<&mut AovSample>) -> Color {
     if depth == 0 {
         return Color::new(0., 0., 0.);
     }
     if let Some(rec) = world.objects.hit(r, 0.00001, f64::INFINITY) {
-        let emitted = rec.mat.emitted(&rec, rec.u, rec.v, rec.p);
-        if let Some(srec) = rec.mat.scatter(r, &rec) {
+        let emitted = to_spectral(rec.mat.emitted(&rec, rec.u, rec.v, rec.p), r);
+        let black = Color::new(0., 0., 0.);
+        let srec = rec.mat.scatter(r, &rec);
+        // the next vertex only has to report what it emits
+        let event = if rec.volume {
+            Event::Volume
+        } else {
+            rec.mat.event(&rec, srec.as_ref())
+        };
+        let mut next = aov.as_ref().map(|a| a.next(event));
+        let mut albedo = black;
+        let mut delta_lights = Vec::new();
+        let mut weight = black;
+        let mut scattered = black;
+        if let Some(mut srec) = srec {
+            albedo = srec.attenuation;
+            srec.attenuation = to_spectral(srec.attenuation, r);
+            if srec.dispersive && r.wavelength.is_some() {
+                // the path now only holds for the hero wavelength
+                srec.attenuation = srec.attenuation * Color::new(3., 0., 0.);
+            }
+            srec.ray.wavelength = r.wavelength;
             if let Some(pdf) = srec.pdf {
-                let light_pdf = HittablePDF::new(world.lights.clone(), rec.p);
-                let mixed_pdf = MixturePDF::new(Arc::new(light_pdf), pdf);
-                let scattered = Ray::new(rec.p, mixed_pdf.generate(), r.time);
-                let pdf_val = mixed_pdf.value(&scattered.dir);
-                emitted
-                    + srec.attenuation * rec.mat.scattering_pdf(r, &rec, &scattered) * ray_color(&scattered, background, world, depth - 1)
-                        / pdf_val
+                let mixed_pdf: Arc<dyn PDF> = if world.lights.objects.is_empty() {
+                    pdf
+                } else {
+                    let light_pdf = HittablePDF::new(world.lights.clone(), rec.p, r.time);
+                    Arc::new(MixturePDF::new(Arc::new(light_pdf), pdf))
+                };
+                let mut ray = Ray::new(rec.p, mixed_pdf.generate(), r.time);
+                ray.wavelength = r.wavelength;
+                let pdf_val = mixed_pdf.value(&ray.dir);
+                delta_lights = direct_lighting(r, &rec, world);
+                for (_, c) in delta_lights.iter_mut() {
+                    *c = srec.attenuation * *c;
+                }
+                weight = srec.attenuation * rec.mat.scattering_pdf(r, &rec, &ray) / pdf_val;
+                scattered = weight * ray_color(&ray, background, world, depth - 1, next.as_mut());
             } else {
-                srec.attenuation * ray_color(&srec.ray, background, world, depth - 1)
+                weight = srec.attenuation;
+                scattered = weight * ray_color(&srec.ray, background, world, depth - 1, next.as_mut());
+            }
+        }
+        let direct = delta_lights.iter().fold(black, |sum, (_, c)| sum + *c);
+        if let Some(a) = aov {
+            let reached_light = weight * next.as_ref().map_or(black, |n| n.emission);
+            a.record_hit(r, &rec);
+            a.albedo = albedo;
+            a.emission = emitted;
+            a.direct = direct + reached_light;
+            a.indirect = scattered - reached_light;
+
+            if let Some(n) = next {
+                for (g, c) in n.light_groups.iter().enumerate() {
+                    a.light_groups[g] += weight * *c;
+                }
+                for (i, c) in n.lpe_values.iter().enumerate() {
+                    a.lpe_values[i] += weight * *c;
+                }
+            }
+            a.add_to_group(rec.light_group, emitted);
+            a.add_to_lpes(&[], emitted);
+            for (g, c) in &delta_lights {
+                a.add_to_group(*g, *c);
+                a.add_to_lpes(&[event], *c);
             }
-        } else {
-            emitted
         }
+        emitted + direct + scattered
     } else {
-        background
+        let c = to_spectral(background.value(&r.dir), r);
+        if let Some(a) = aov {
+            a.emission = c;
+            a.add_to_group(0, c);
+            a.add_to_lpes(&[], c);
+        }
+        c
     }
 }
 
@@ -212,7 +356,10 @@ fn cornell_box() -> World {
 
     world.add(Arc::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
     world.add(Arc::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    let ceil_light = Arc::new(FlipFace::new(Arc::new(XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light))));
+    let ceil_light = Arc::new(LightGroup::new(
+        Arc::new(FlipFace::new(Arc::new(XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light)))),
+        1,
+    ));
     world.add(ceil_light.clone());
     lights.add(ceil_light);
     world.add(Arc::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white.clone())));
@@ -239,7 +386,237 @@ fn cornell_box() -> World {
 
     World {
         objects: world,
-        lights: Arc::new(lights),
+        lights: Arc::new(LightList::new(lights, 0.0, 1.0)),
+        delta_lights: Vec::new(),
+        light_groups: vec!["ceiling".to_string()],
+    }
+}
+
+// A subject in focus in front of a field of small, distant lights.
+fn bokeh() -> World {
+    let mut world = HittableList::new();
+    let mut lights = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(Color::new(0.4, 0.4, 0.4)));
+    world.add(Arc::new(XZRect::new(-50.0, 50.0, -50.0, 50.0, 0.0, ground)));
+    let gold = Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.05));
+    world.add(Arc::new(Sphere::new(Point3::new(0.0, 0.5, 0.0), 0.5, gold)));
+    let key = Arc::new(DiffuseLight::new_color(Color::new(4.0, 4.0, 4.0)));
+    let key_light = Arc::new(FlipFace::new(Arc::new(XZRect::new(-2.0, 2.0, -2.0, 2.0, 6.0, key))));
+    world.add(key_light.clone());
+    lights.add(key_light);
+
+    // far enough behind the subject that each blurs into an image of the aperture
+    for _ in 0..48 {
+        let center = Point3::new(random_range(-8.0, 8.0), random_range(0.5, 6.0), random_range(-70.0, -40.0));
+        let light = Arc::new(DiffuseLight::new_color(4000.0 * Color::random_range(0.3, 1.0)));
+        let bulb = Arc::new(Sphere::new(center, 0.03, light));
+        world.add(bulb.clone());
+        lights.add(bulb);
+    }
+
+    World {
+        objects: world,
+        lights: Arc::new(LightList::new(lights, 0.0, 1.0)),
+        delta_lights: Vec::new(),
+        light_groups: Vec::new(),
+    }
+}
+
+// A fence punched out of a rect by a checker pattern, and a leaf cut out of
+// its image by the alpha channel.
+fn cutouts() -> World {
+    let mut world = HittableList::new();
+
+    let checker = Arc::new(CheckerTexture::new_solid(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    let ground = Arc::new(Lambertian::new_textured(checker));
+    world.add(Arc::new(XZRect::new(-10.0, 10.0, -10.0, 10.0, 0.0, ground)));
+
+    let holes = Arc::new(CheckerTexture::new_solid(Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0)));
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let fence = Arc::new(AlphaMask::new(white, holes, AlphaMode::Threshold(0.5)));
+    world.add(Arc::new(XYRect::new(-3.0, 3.0, 0.0, 2.0, -1.0, fence)));
+
+    let leaf = Arc::new(ImageTexture::new(std::path::Path::new("textures/leaf.png")));
+    let leaf_surface = Arc::new(Lambertian::new_textured(leaf.clone()));
+    let leaf_alpha = Arc::new(AlphaChannel::new(leaf));
+    let leaf_mat = Arc::new(AlphaMask::new(leaf_surface, leaf_alpha, AlphaMode::Stochastic));
+    world.add(Arc::new(XYRect::new(-1.0, 1.0, 0.5, 2.5, 1.0, leaf_mat)));
+
+    World {
+        objects: world,
+        lights: Arc::new(LightList::new(HittableList::new(), 0.0, 1.0)),
+        delta_lights: Vec::new(),
+        light_groups: Vec::new(),
+    }
+}
+
+// A row of spheres under the sky, one for each layered or blended material.
+fn materials() -> World {
+    let mut world = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.add(Arc::new(XZRect::new(-50.0, 50.0, -50.0, 50.0, 0.0, ground)));
+
+    // steel with patches of rust where the noise is bright
+    let steel = Arc::new(Metal::new(Color::new(0.8, 0.8, 0.85), 0.05));
+    let rust = Arc::new(Lambertian::new(Color::new(0.45, 0.2, 0.08)));
+    let patches = Arc::new(NoiseTexture::new_scaled(4.0));
+    let rusty = Arc::new(MixMaterial::new(steel, rust, patches));
+    world.add(Arc::new(Sphere::new(Point3::new(-2.25, 1.0, 0.0), 1.0, rusty)));
+
+    // red paint under a clear coat, and steel under a 300 nm oxide film
+    let paint = Arc::new(Lambertian::new(Color::new(0.6, 0.05, 0.05)));
+    let car_paint = Arc::new(Coated::new(paint, 1.5, 0.01, Color::new(0.0, 0.0, 0.0)));
+    world.add(Arc::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, car_paint)));
+    let steel = Arc::new(Metal::new(Color::new(0.6, 0.6, 0.65), 0.0));
+    let tempered = Arc::new(Coated::new_thin_film(steel, 2.0, 300.0, 2.5));
+    world.add(Arc::new(Sphere::new(Point3::new(2.25, 1.0, 0.0), 1.0, tempered)));
+    let bubble = Arc::new(ThinFilm::new(1.33, 500.0));
+    world.add(Arc::new(Sphere::new(Point3::new(1.0, 0.6, 2.0), 0.6, bubble)));
+
+    World {
+        objects: world,
+        lights: Arc::new(LightList::new(HittableList::new(), 0.0, 1.0)),
+        delta_lights: Vec::new(),
+        light_groups: Vec::new(),
+    }
+}
+
+// A dark stage lit only by lights that rays never hit.
+fn stage() -> World {
+    let mut world = HittableList::new();
+
+    let grey = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.add(Arc::new(XZRect::new(-50.0, 50.0, -50.0, 50.0, 0.0, grey.clone())));
+    world.add(Arc::new(XYRect::new(-50.0, 50.0, 0.0, 50.0, -4.0, grey)));
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    world.add(Arc::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, white.clone())));
+    let metal = Arc::new(Metal::new(Color::new(0.8, 0.85, 0.88), 0.2));
+    world.add(Arc::new(Sphere::new(Point3::new(2.5, 0.7, 1.0), 0.7, metal)));
+    let pillar = Arc::new(RectPrism::new(Point3::new(-3.5, 0.0, -1.0), Point3::new(-2.5, 2.5, 0.0), white));
+    world.add(pillar);
+
+    // a warm lamp, fading out before it reaches the back wall
+    let lamp = Arc::new(PointLight::new_with_range(
+        Point3::new(-2.0, 3.0, 2.0),
+        Color::new(20.0, 16.0, 12.0),
+        8.0,
+    ));
+    // a spot on the white sphere
+    let spot = Arc::new(SpotLight::new(
+        Point3::new(1.0, 6.0, 3.0),
+        Vec3::new(-1.0, -5.0, -3.0),
+        Color::new(60.0, 60.0, 60.0),
+        8.0,
+        15.0,
+    ));
+    let delta_lights: Vec<Arc<dyn Light>> = vec![
+        Arc::new(GroupedLight::new(lamp, 1)),
+        Arc::new(GroupedLight::new(spot, 2)),
+        // a cold fill low on the right
+        Arc::new(PointLight::new(Point3::new(4.0, 0.5, 3.0), Color::new(2.0, 3.0, 4.0))),
+        // dim moonlight from behind
+        Arc::new(DirectionalLight::new(Vec3::new(0.5, -1.0, 1.0), Color::new(0.05, 0.06, 0.1))),
+    ];
+
+    World {
+        objects: world,
+        lights: Arc::new(LightList::new(HittableList::new(), 0.0, 1.0)),
+        delta_lights,
+        light_groups: vec!["lamp".to_string(), "spot".to_string()],
+    }
+}
+
+// Dispersive glass in front of thin bright strips, for the spectral mode.
+fn prisms() -> World {
+    let mut world = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(Color::new(0.4, 0.4, 0.4)));
+    world.add(Arc::new(XZRect::new(-50.0, 50.0, -50.0, 50.0, 0.0, ground)));
+    let strip = Arc::new(DiffuseLight::new_color(Color::new(8.0, 8.0, 8.0)));
+    for i in -6..=6 {
+        let x = i as f64;
+        world.add(Arc::new(XYRect::new(x - 0.05, x + 0.05, 0.0, 4.0, -4.0, strip.clone())));
+    }
+
+    // BK7 crown glass, and a flint with far more dispersion than any real glass
+    let crown = Ior::Sellmeier([1.03961212, 0.231792344, 1.01046945], [0.00600069867, 0.0200179144, 103.560653]);
+    let flint = Ior::Cauchy(1.7, 0.03);
+    let crown = Arc::new(Dielectric::new_dispersive(crown));
+    let flint = Arc::new(Dielectric::new_dispersive(flint));
+    world.add(Arc::new(Sphere::new(Point3::new(-1.3, 1.2, 0.0), 1.2, crown)));
+    world.add(Arc::new(Sphere::new(Point3::new(1.3, 1.2, 0.0), 1.2, flint)));
+
+    let light = Arc::new(DiffuseLight::new_color(Color::new(4.0, 4.0, 4.0)));
+    let key = Arc::new(FlipFace::new(Arc::new(XZRect::new(-2.0, 2.0, -1.0, 3.0, 8.0, light))));
+    world.add(key.clone());
+    let mut lights = HittableList::new();
+    lights.add(key);
+
+    World {
+        objects: world,
+        lights: Arc::new(LightList::new(lights, 0.0, 1.0)),
+        delta_lights: Vec::new(),
+        light_groups: Vec::new(),
+    }
+}
+
+// A cube tumbling along a curve, a ball bouncing and marbles rolling past,
+// for a motion blurred sequence two seconds long.
+fn motion() -> World {
+    let mut world = HittableList::new();
+    let mut moving = HittableList::new();
+
+    let checker = Arc::new(CheckerTexture::new_solid(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    let ground = Arc::new(Lambertian::new_textured(checker));
+    world.add(Arc::new(XZRect::new(-50.0, 50.0, -50.0, 50.0, 0.0, ground)));
+
+    // keys bunched up over the top, where the cube slows down
+    let unit = Vec3::new(1.0, 1.0, 1.0);
+    let red = Arc::new(Lambertian::new(Color::new(0.7, 0.1, 0.1)));
+    let cube = Arc::new(RectPrism::new(Point3::new(-0.5, -0.5, -0.5), Point3::new(0.5, 0.5, 0.5), red));
+    let path = Animation::new(
+        vec![
+            Keyframe::new(0.0, Vec3::new(-4.0, 0.5, 0.0), Vec3::new(0.0, 0.0, 0.0), unit),
+            Keyframe::new(0.6, Vec3::new(-1.0, 2.5, -1.0), Vec3::new(90.0, 45.0, 0.0), unit),
+            Keyframe::new(0.8, Vec3::new(0.5, 2.7, -1.0), Vec3::new(120.0, 60.0, 0.0), unit),
+            Keyframe::new(2.0, Vec3::new(4.0, 0.5, 0.0), Vec3::new(360.0, 180.0, 0.0), unit),
+        ],
+        Interpolation::Bezier,
+    );
+    moving.add(Arc::new(Animated::new(cube, Arc::new(path))));
+
+    // squashed as it lands, stretched as it leaves the ground
+    let gold = Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.1));
+    let ball = Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.5, gold));
+    let bounce = Animation::new(
+        vec![
+            Keyframe::new(0.0, Vec3::new(1.5, 2.5, 1.5), Vec3::new(0.0, 0.0, 0.0), unit),
+            Keyframe::new(0.9, Vec3::new(1.5, 0.4, 1.5), Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.2, 0.8, 1.2)),
+            Keyframe::new(1.0, Vec3::new(1.5, 0.6, 1.5), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.9, 1.2, 0.9)),
+            Keyframe::new(2.0, Vec3::new(1.5, 2.5, 1.5), Vec3::new(0.0, 0.0, 0.0), unit),
+        ],
+        Interpolation::Linear,
+    );
+    moving.add(Arc::new(Animated::new(ball, Arc::new(bounce))));
+
+    for i in 0..5 {
+        let z = 2.0 + 0.6 * i as f64;
+        let speed = random_range(1.0, 3.0);
+        let glass = Arc::new(Dielectric::new(1.5));
+        let (start, end) = (Point3::new(-speed, 0.2, z), Point3::new(speed, 0.2, z));
+        moving.add(Arc::new(MovingSphere::new(start, end, 0.0, 2.0, 0.2, glass)));
+    }
+    // bounded per part of the sequence, so each frame's rays only test the
+    // objects that pass near them then
+    world.add(Arc::new(BVHNode::new_from_list(moving, 0.0, 2.0)));
+
+    World {
+        objects: world,
+        lights: Arc::new(LightList::new(HittableList::new(), 0.0, 2.0)),
+        delta_lights: Vec::new(),
+        light_groups: Vec::new(),
     }
 }
 
@@ -384,59 +761,140 @@ fn cornell_box() -> World {
 //     objects
 // }
 
-fn render_row(world: Arc<World>, row_count: Arc<Mutex<u32>>, data: Arc<ImageData>, output: Arc<Mutex<image::RgbImage>>) {
-    let mut rng = thread_rng();
+// Renders samples [first_sample, last_sample) of each pixel in the tiles
+// it claims. Threads take the next unclaimed tile until none are left.
+fn render_tiles(
+    world: Arc<World>,
+    tiles: Arc<Vec<Tile>>,
+    next_tile: Arc<AtomicUsize>,
+    data: Arc<ImageData>,
+    output: Arc<Mutex<Film>>,
+    first_sample: usize,
+    last_sample: usize,
+) {
+    set_sampler(new_sampler(data.sampler, data.samples_per_pixel, data.seed));
     loop {
-        let current_row: u32;
-        {
-            let mut rc = row_count.lock().unwrap();
-            current_row = *rc;
-            *rc += 1;
-            if *rc > data.height {
-                return;
-            }
-            eprint!("\rScanlines Remaining: {:04}", data.height - *rc);
+        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+        if index >= tiles.len() {
+            return;
         }
-        for i in 0..data.width {
-            let mut pixel_color = Color::new(0., 0., 0.);
-
-            for _ in 0..data.samples_per_pixel {
-                let x: f64 = rng.gen();
-                let y: f64 = rng.gen();
-                let u = (i as f64 + x) / (data.width as f64 - 1.0);
-                let v = (current_row as f64 + y) / (data.height as f64 - 1.0);
-                let r = data.camera.get_ray(u, v);
-                pixel_color += ray_color(&r, data.background, &world, data.max_depth);
+        eprint!("\rTiles Remaining: {:04}", tiles.len() - index - 1);
+        let t = tiles[index];
+        let (mut film_tile, prior) = {
+            let film = output.lock().unwrap();
+            let mut prior = Vec::new();
+            for y in t.y0..t.y1 {
+                for x in t.x0..t.x1 {
+                    prior.push(film.pixel_stats(x, y));
+                }
             }
-            {
-                let o = &output.clone();
-                let mut o_mut = o.lock().unwrap();
-                o_mut.put_pixel(
-                    i,
-                    data.height - current_row - 1,
-                    image::Rgb(write_color(pixel_color, data.samples_per_pixel)),
-                )
+            (film.tile(t.x0, t.y0, t.x1, t.y1), prior)
+        };
+        let mut prior = prior.into_iter();
+        for film_row in t.y0..t.y1 {
+            let current_row = data.height - film_row - 1;
+            for i in t.x0..t.x1 {
+                let mut stats = prior.next().unwrap();
+                if data.adaptive.as_ref().is_some_and(|a| a.converged(&stats)) {
+                    continue;
+                }
+                with_sampler(|s| s.start_pixel(i, current_row));
+                let mut pass_stats = PixelStats::new();
+                for sample in first_sample..last_sample {
+                    with_sampler(|s| s.start_sample(sample));
+                    let x = i as f64 + random_f64();
+                    let y = current_row as f64 + random_f64();
+                    let r = data.camera.get_ray(x / data.width as f64, y / data.height as f64);
+                    let mut aov = (!data.aovs.is_empty()).then(|| AovSample::new(data.light_groups, data.lpes.clone()));
+                    let hero = data.spectral.then(|| random_range(LAMBDA_MIN, LAMBDA_MAX));
+                    // a ray the lens blocks still counts, as a black sample
+                    let mut color = match r {
+                        Some(mut r) => {
+                            r.wavelength = hero;
+                            ray_color(&r, data.background.as_ref(), &world, data.max_depth, aov.as_mut())
+                        }
+                        None => Color::new(0.0, 0.0, 0.0),
+                    };
+                    if let Some(hero) = hero {
+                        color = spectral_to_rgb(color, hero);
+                        if let Some(a) = aov.as_mut() {
+                            a.direct = spectral_to_rgb(a.direct, hero);
+                            a.indirect = spectral_to_rgb(a.indirect, hero);
+                            a.emission = spectral_to_rgb(a.emission, hero);
+                            for c in a.light_groups.iter_mut().chain(a.lpe_values.iter_mut()) {
+                                *c = spectral_to_rgb(*c, hero);
+                            }
+                        }
+                    }
+                    // the film stores rows top down
+                    film_tile.add_sample(x, data.height as f64 - y, color);
+                    if let Some(a) = aov {
+                        film_tile.add_aov_sample(i, film_row, &a);
+                    }
+                    stats.add(color);
+                    pass_stats.add(color);
+                    if data.adaptive.as_ref().is_some_and(|a| a.converged(&stats)) {
+                        break;
+                    }
+                }
+                film_tile.record_stats(i, film_row, &pass_stats);
             }
         }
+        output.lock().unwrap().submit(index, film_tile);
     }
 }
 
 fn main() {
-    let threads = 8;
+    let threads = thread::available_parallelism().map_or(8, |n| n.get());
     // image
     let scene = 5;
     let mut aspect_ratio = 16.0 / 9.0;
     let mut image_width = 600;
-    let mut background = Color::new(0.7, 0.8, 1.0);
+    let (sun_elevation, sun_azimuth) = sun_position(37.8, -122.4, 2021, 6, 21, 20.0);
+    let mut background: Arc<dyn Environment> = Arc::new(PreethamSky::new_from_angles(sun_elevation, sun_azimuth, 3.0, 1.0));
     let mut lookfrom = Point3::new(13.0, 2.0, 3.0);
     let mut lookat = Point3::new(0.0, 0.0, 0.0);
     let mut dist_to_focus = 10.0;
     let mut aperture = 0.0;
     let vup = Vec3::new(0.0, 1.0, 0.0);
     let mut vfov = 20.0;
+    // physical camera settings replace vfov and aperture, and expose the image
+    let mut lens: Option<CameraSettings> = None;
+    // scale of the scene, for the size of the aperture
+    let units_per_mm = 0.001;
+    let mut aperture_shape = ApertureShape::Circle;
+    let mut cat_eye = 0.0;
+    let projection;
+    // moves the whole camera rig, on the same clock as animated objects
+    let mut camera_animation = Arc::new(Animation::still());
+    // frames first..=last, each at frame / fps, go to numbered files; None
+    // renders a single image over time 0 to 1
+    let frames: Option<(u32, u32)>;
+    let fps = 24.0;
     let world;
     let mut samples_per_pixel = 1000;
     let max_depth = 50;
+    let mut spectral = false;
+    let mut sampler = SamplerType::Independent;
+    // one pixel wide, so each sample only counts towards its own pixel
+    let mut filter: Arc<dyn Filter> = Arc::new(BoxFilter::new(0.5));
+    let mut tone_operator = ToneMapOperator::Linear;
+    let mut tile_order = TileOrder::Scanline;
+    // the rest are off unless a scene turns them on
+    let mut adaptive = None;
+    // guided by the albedo and normal passes when they are enabled
+    let mut denoiser = None;
+    // passes written to output.exr as extra layers
+    let mut aovs = Vec::new();
+    // each also gets its own pass, as does every light group of the scene,
+    // when there are any passes
+    let mut lpes = Vec::new();
+    // passes of this many samples per pixel refine the whole image, with a
+    // checkpoint after each so an interrupted render can be resumed
+    let mut checkpoint_every: Option<usize> = None;
+    // the same seed gives a bit-identical image, whatever the thread count
+    let seed = 0;
+    seed_random(seed);
 
     match scene {
         // 0 => {
@@ -457,17 +915,209 @@ fn main() {
         //     lookfrom = Point3::new(23.0, 3.0, 6.0);
         //     lookat = Point3::new(0.0, 2.0, 0.0);
         // }
+        7 => {
+            world = bokeh();
+            lookfrom = Point3::new(0.0, 1.0, 9.0);
+            lookat = Point3::new(0.0, 0.5, 0.0);
+            dist_to_focus = (lookat - lookfrom).length();
+            background = Arc::new(ConstantEnvironment::new(Color::new(0.0, 0.0, 0.0)));
+            lens = Some(CameraSettings::new(135.0, 2.0, 36.0, 24.0, 1.0 / 60.0, 3200.0));
+            aperture_shape = ApertureShape::Blades { count: 6, rotation: 15.0 };
+            cat_eye = 0.5;
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            projection = Projection::Perspective;
+            frames = None;
+        }
+        8 => {
+            world = bokeh();
+            lookfrom = Point3::new(0.0, 1.0, 9.0);
+            lookat = Point3::new(0.0, 0.5, 0.0);
+            dist_to_focus = (lookat - lookfrom).length();
+            background = Arc::new(ConstantEnvironment::new(Color::new(0.0, 0.0, 0.0)));
+            lens = Some(CameraSettings::new(135.0, 2.0, 36.0, 24.0, 1.0 / 60.0, 3200.0));
+            let mask = ApertureMask::new(std::path::Path::new("textures/aperture_heart.png"));
+            aperture_shape = ApertureShape::Mask(Arc::new(mask));
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(LanczosFilter::new(3.0));
+            tone_operator = ToneMapOperator::Aces;
+            projection = Projection::Perspective;
+            frames = None;
+        }
+        9 => {
+            world = cutouts();
+            lookfrom = Point3::new(0.0, 1.5, 6.0);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            dist_to_focus = 6.0;
+            vfov = 40.0;
+            sampler = SamplerType::Stratified;
+            filter = Arc::new(TentFilter::new(1.0));
+            projection = Projection::Perspective;
+            frames = None;
+        }
+        10 => {
+            world = materials();
+            lookfrom = Point3::new(0.0, 2.5, 10.0);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+            aovs = vec![
+                Aov::Albedo,
+                Aov::Normal,
+                Aov::Position,
+                Aov::Depth,
+                Aov::Uv,
+                Aov::ObjectId,
+                Aov::MaterialId,
+            ];
+            denoiser = Some(Denoiser::new(5, 4.0, 128.0, 0.1));
+            sampler = SamplerType::Halton;
+            filter = Arc::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0));
+            tone_operator = ToneMapOperator::Reinhard(4.0);
+            projection = Projection::Perspective;
+            frames = None;
+        }
+        11 => {
+            world = stage();
+            lookfrom = Point3::new(0.0, 3.0, 10.0);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            background = Arc::new(ConstantEnvironment::new(Color::new(0.0, 0.0, 0.0)));
+            vfov = 35.0;
+            aovs = vec![Aov::Direct, Aov::Indirect, Aov::Emission];
+            lpes = vec![
+                LightPathExpression::new("C D L"),
+                LightPathExpression::new("C D [DSV]+ L"),
+                LightPathExpression::new("C D S+ L"),
+            ];
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(BlackmanHarrisFilter::new(2.0));
+            tone_operator = ToneMapOperator::Hable;
+            projection = Projection::Perspective;
+            frames = None;
+        }
+        12 => {
+            world = materials();
+            lookfrom = Point3::new(0.0, 2.5, 10.0);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+            background = Arc::new(EnvironmentMap::new(std::path::Path::new("textures/studio.hdr"), 1.0, 30.0));
+            adaptive = Some(AdaptiveSampling::new(64, 0.01));
+            aovs = vec![Aov::SampleCount];
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            tile_order = TileOrder::Spiral;
+            projection = Projection::Perspective;
+            frames = None;
+        }
+        13 => {
+            world = prisms();
+            lookfrom = Point3::new(0.0, 2.0, 9.0);
+            lookat = Point3::new(0.0, 1.2, 0.0);
+            background = Arc::new(ConstantEnvironment::new(Color::new(0.0, 0.0, 0.0)));
+            vfov = 30.0;
+            spectral = true;
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            projection = Projection::Perspective;
+            frames = None;
+        }
+        14 => {
+            // the bokeh scene through a real double Gauss lens
+            world = bokeh();
+            lookfrom = Point3::new(0.0, 1.0, 4.0);
+            lookat = Point3::new(0.0, 0.5, 0.0);
+            background = Arc::new(ConstantEnvironment::new(Color::new(0.0, 0.0, 0.0)));
+            lens = Some(CameraSettings::new(50.0, 2.0, 36.0, 24.0, 1.0 / 60.0, 3200.0));
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            tile_order = TileOrder::Spiral;
+            projection = Projection::Realistic {
+                prescription: "lenses/dgauss.50mm.lens",
+            };
+            checkpoint_every = Some(64);
+            frames = None;
+        }
+        15 => {
+            // two seconds at 24 fps, with the camera dollying in
+            world = motion();
+            lookfrom = Point3::new(0.0, 2.0, 14.0);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+            samples_per_pixel = 256;
+            camera_animation = Arc::new(Animation::new(
+                vec![
+                    Keyframe::new(0.0, Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)),
+                    Keyframe::new(2.0, Vec3::new(0.0, 0.0, -3.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)),
+                ],
+                Interpolation::Bezier,
+            ));
+            checkpoint_every = Some(64);
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            tile_order = TileOrder::Hilbert;
+            projection = Projection::Perspective;
+            frames = Some((0, 47));
+        }
+        16 => {
+            world = materials();
+            lookfrom = Point3::new(0.0, 2.5, 10.0);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            projection = Projection::Orthographic { height: 4.0 };
+            frames = None;
+        }
+        17 => {
+            world = materials();
+            lookfrom = Point3::new(0.0, 1.5, 3.5);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            aspect_ratio = 1.0;
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            projection = Projection::Fisheye { fov: 180.0 };
+            frames = None;
+        }
+        18 => {
+            world = materials();
+            lookfrom = Point3::new(0.0, 1.0, 3.5);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            aspect_ratio = 2.0;
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            projection = Projection::Equirectangular;
+            frames = None;
+        }
+        19 => {
+            world = materials();
+            lookfrom = Point3::new(0.0, 1.0, 3.5);
+            lookat = Point3::new(0.0, 1.0, 0.0);
+            aspect_ratio = 1.5;
+            sampler = SamplerType::Sobol;
+            filter = Arc::new(GaussianFilter::new(1.5, 2.0));
+            tone_operator = ToneMapOperator::Aces;
+            projection = Projection::CubeMap;
+            frames = None;
+        }
         _ => {
             world = cornell_box();
             lookfrom = Point3::new(278.0, 278.0, -800.0);
             lookat = Point3::new(278.0, 278.0, 0.0);
             dist_to_focus = 800.0;
-            background = Color::new(0.0, 0.0, 0.0);
+            background = Arc::new(ConstantEnvironment::new(Color::new(0.0, 0.0, 0.0)));
             vfov = 40.0;
             aspect_ratio = 1.0;
             samples_per_pixel = 5000;
             image_width = 600;
             aperture = 10.0;
+            projection = Projection::Perspective;
+            frames = None;
         } // 6 => {
           //     world = cornell_smoke();
           //     lookfrom = Point3::new(278.0, 278.0, -800.0);
@@ -489,36 +1139,208 @@ fn main() {
           // }
     }
     let image_height = (image_width as f64 / aspect_ratio).round() as u32;
-    let camera = Camera::new(lookfrom, lookat, vup, vfov, aspect_ratio, aperture, dist_to_focus, 0., 1.);
+    let camera: Arc<dyn Camera> = match projection {
+        Projection::Perspective => {
+            let camera = match &lens {
+                Some(lens) => PerspectiveCamera::new_physical(lookfrom, lookat, vup, lens, aspect_ratio, units_per_mm, dist_to_focus),
+                None => PerspectiveCamera::new(lookfrom, lookat, vup, vfov, aspect_ratio, aperture, dist_to_focus, 0., 1.),
+            };
+            Arc::new(camera.with_aperture_shape(aperture_shape).with_cat_eye(cat_eye))
+        }
+        Projection::Orthographic { height } => Arc::new(OrthographicCamera::new(lookfrom, lookat, vup, height, aspect_ratio, 0., 1.)),
+        Projection::Fisheye { fov } => Arc::new(FisheyeCamera::new(lookfrom, lookat, vup, fov, aspect_ratio, 0., 1.)),
+        Projection::Equirectangular => Arc::new(EquirectangularCamera::new(lookfrom, lookat, vup, 0., 1.)),
+        Projection::CubeMap => Arc::new(CubeMapCamera::new(lookfrom, lookat, vup, 0., 1.)),
+        Projection::Realistic { prescription } => Arc::new(RealisticCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            load_lens(std::path::Path::new(prescription)),
+            &lens.expect("a realistic camera needs camera settings"),
+            aspect_ratio,
+            units_per_mm,
+        )),
+    };
+    // camera settings expose the image, as on a real camera
+    let tone_mapper = ToneMapper::new(tone_operator, lens.map_or(0.0, |l| l.exposure_ev()));
+    let light_groups = world.light_groups.len() + 1;
+    let lpes = Arc::new(lpes);
+    if !aovs.is_empty() {
+        aovs.extend((0..light_groups as u32).map(Aov::LightGroup));
+        aovs.extend((0..lpes.len()).map(Aov::Lpe));
+    }
 
-    let image_data = Arc::new(ImageData {
+    let base_data = ImageData {
         height: image_height,
         width: image_width,
         samples_per_pixel,
         max_depth,
+        spectral,
+        sampler,
+        seed,
+        adaptive,
+        aovs,
+        light_groups,
+        lpes,
         camera,
         background,
-    });
-    let image = Arc::new(Mutex::new(image::ImageBuffer::new(image_data.width, image_data.height)));
-    let row_count = Arc::new(Mutex::new(0));
-
-    let world_pointer = Arc::new(world);
-
-    // render
-    let mut handles = vec![];
-    for _i in 0..threads {
-        let w = world_pointer.clone();
-        let rc = row_count.clone();
-        let id = image_data.clone();
-        let i = image.clone();
-        let handle = thread::spawn(move || render_row(w, rc, id, i));
-        handles.push(handle)
+    };
+    let world_pointer = Arc::new(world.with_environment(base_data.background.clone()));
+    let tiles = Arc::new(make_tiles(base_data.width, base_data.height, 16, tile_order));
+
+    // a sequence keeps time in seconds; without camera settings the shutter
+    // is open for half of each frame
+    let shutter = lens.map_or(0.5 / fps, |l| l.shutter);
+    let (first_frame, last_frame) = frames.unwrap_or((0, 0));
+    for frame in first_frame..=last_frame {
+        // a still is exposed over the scene's 0..1 interval, a frame from
+        // frame / fps for the length of the shutter
+        let (time0, time1) = match frames {
+            Some(_) => (frame as f64 / fps, frame as f64 / fps + shutter),
+            None => (0.0, 1.0),
+        };
+        let image_data = Arc::new(ImageData {
+            camera: Arc::new(AnimatedCamera::new(
+                base_data.camera.clone(),
+                camera_animation.clone(),
+                time0,
+                time1,
+            )),
+            ..base_data.clone()
+        });
+        // numbered by frame when rendering a sequence
+        let file = |stem: &str, ext: &str| match frames {
+            Some(_) => format!("{}.{:04}.{}", stem, frame, ext),
+            None => format!("{}.{}", stem, ext),
+        };
+        if frames.is_some() {
+            eprintln!("\nFrame {}", frame);
+        }
+        // without checkpoints the frame is rendered in a single pass
+        let pass_samples = checkpoint_every.unwrap_or(image_data.samples_per_pixel);
+        let checkpoint = file("checkpoint", "film");
+        let fingerprint = checkpoint_fingerprint(&format!(
+            "scene {} spp {} depth {} spectral {} seed {} sampler {:?} filter {:?}",
+            scene, image_data.samples_per_pixel, image_data.max_depth, image_data.spectral, seed, sampler, filter
+        ));
+        let resumed = checkpoint_every
+            .and_then(|_| Film::load_checkpoint(&checkpoint, fingerprint, image_data.width, image_data.height, filter.clone()));
+        let (film, mut samples_taken) = match resumed {
+            Some((film, taken)) => {
+                eprintln!("Resuming from {} with {} samples per pixel", checkpoint, taken);
+                (film, taken)
+            }
+            None => (Film::new(image_data.width, image_data.height, filter.clone()), 0),
+        };
+        let mut film = film
+            .with_aovs(&image_data.aovs)
+            .with_layer_name(Aov::LightGroup(0), "light_default");
+        for (g, name) in world_pointer.light_groups.iter().enumerate() {
+            film = film.with_layer_name(Aov::LightGroup(g as u32 + 1), &format!("light_{}", name));
+        }
+        for (i, lpe) in image_data.lpes.iter().enumerate() {
+            film = film.with_layer_name(Aov::Lpe(i), &format!("lpe_{}", lpe.source.replace(' ', "")));
+        }
+        let film = Arc::new(Mutex::new(film));
+
+        // render
+        while samples_taken < image_data.samples_per_pixel {
+            let pass_end = (samples_taken + pass_samples).min(image_data.samples_per_pixel);
+            eprintln!("\nSamples {}..{} of {}", samples_taken, pass_end, image_data.samples_per_pixel);
+            film.lock().unwrap().begin_pass();
+            let next_tile = Arc::new(AtomicUsize::new(0));
+            let mut handles = vec![];
+            for _i in 0..threads {
+                let w = world_pointer.clone();
+                let t = tiles.clone();
+                let nt = next_tile.clone();
+                let id = image_data.clone();
+                let i = film.clone();
+                let handle = thread::spawn(move || render_tiles(w, t, nt, id, i, samples_taken, pass_end));
+                handles.push(handle)
+            }
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            samples_taken = pass_end;
+
+            if checkpoint_every.is_some() {
+                let film = film.lock().unwrap();
+                film.save_checkpoint(&checkpoint, fingerprint, samples_taken).unwrap();
+                film.save(&file("output", "png"), &tone_mapper);
+            }
+        }
+
+        let film = film.lock().unwrap();
+        for ext in ["png", "exr"] {
+            film.save(&file("output", ext), &tone_mapper);
+        }
+        if image_data.adaptive.is_some() {
+            film.sample_heatmap().save(file("samples", "png")).unwrap();
+        }
+        if let Some(d) = &denoiser {
+            let denoised = d.denoise(&film);
+            for ext in ["png", "exr"] {
+                save_image(&file("denoised", ext), film.width, film.height, &denoised, &tone_mapper);
+            }
+        }
+        // the outputs are complete, so a later run starts the frame afresh
+        if checkpoint_every.is_some() {
+            let _ = std::fs::remove_file(&checkpoint);
+        }
     }
+    eprint!("\nDone\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for handle in handles {
-        handle.join().unwrap();
+    fn render_cornell(threads: usize) -> Vec<[u64; 3]> {
+        seed_random(0);
+        let world = Arc::new(cornell_box());
+        let lookfrom = Point3::new(278.0, 278.0, -800.0);
+        let lookat = Point3::new(278.0, 278.0, 0.0);
+        let camera = PerspectiveCamera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), 40.0, 1.0, 10.0, 800.0, 0., 1.);
+        let (width, height) = (24, 24);
+        let data = Arc::new(ImageData {
+            height,
+            width,
+            samples_per_pixel: 8,
+            max_depth: 8,
+            spectral: false,
+            sampler: SamplerType::Sobol,
+            seed: 3,
+            adaptive: None,
+            aovs: Vec::new(),
+            light_groups: 2,
+            lpes: Arc::new(Vec::new()),
+            camera: Arc::new(camera),
+            background: Arc::new(ConstantEnvironment::new(Color::new(0.0, 0.0, 0.0))),
+        });
+        let tiles = Arc::new(make_tiles(width, height, 8, TileOrder::Spiral));
+        let film = Arc::new(Mutex::new(Film::new(width, height, Arc::new(GaussianFilter::new(1.5, 2.0)))));
+        film.lock().unwrap().begin_pass();
+        let next_tile = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let (w, t, nt, d, f) = (world.clone(), tiles.clone(), next_tile.clone(), data.clone(), film.clone());
+                thread::spawn(move || render_tiles(w, t, nt, d, f, 0, 8))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let pixels = film.lock().unwrap().pixels();
+        pixels.iter().map(|c| c.a.map(f64::to_bits)).collect()
     }
 
-    image.lock().unwrap().save("output.png").unwrap();
-    eprint!("\nDone\n");
+    #[test]
+    fn renders_are_identical_for_any_thread_count() {
+        let single = render_cornell(1);
+        assert!(single.iter().any(|p| p[0] != 0));
+        assert_eq!(single, render_cornell(4));
+        assert_eq!(single, render_cornell(4));
+    }
 }
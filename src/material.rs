@@ -1,5 +1,6 @@
 use crate::color::*;
 use crate::hittable::*;
+use crate::lpe::Event;
 use crate::onb::ONB;
 use crate::pdf::random_cosine_direction;
 use crate::prelude::CosinePDF;
@@ -8,10 +9,27 @@ use crate::ray::*;
 use crate::texture::*;
 use crate::utils::*;
 use crate::vec3::*;
+use std::cell::Cell;
 use std::f64::consts::PI;
 use std::sync::Arc;
 
+thread_local! {
+    static NEXT_MATERIAL_ID: Cell<u32> = const { Cell::new(1) };
+}
+
+// Materials are numbered in the order the scene creates them, which is the
+// same on every run and for every frame of a sequence.
+pub fn new_material_id() -> u32 {
+    NEXT_MATERIAL_ID.with(|n| {
+        let id = n.get();
+        n.set(id + 1);
+        id
+    })
+}
+
 pub trait Material: Send + Sync {
+    // the material id pass value, from new_material_id
+    fn id(&self) -> u32;
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
     fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         0.0
@@ -19,22 +37,40 @@ pub trait Material: Send + Sync {
     fn emitted(&self, _rec: &HitRecord, _u: f64, _v: f64, _p: Point3) -> Color {
         Color::new(0., 0., 0.)
     }
+    fn alpha_test(&self, _rec: &HitRecord) -> bool {
+        true
+    }
+    fn average_emitted(&self) -> Color {
+        Color::new(0., 0., 0.)
+    }
+    // how light path expressions see a bounce off this material, given the
+    // scatter it took if it took one
+    fn event(&self, _rec: &HitRecord, _srec: Option<&ScatterRecord>) -> Event {
+        Event::Diffuse
+    }
 }
 
 pub struct ScatterRecord {
     pub ray: Ray,
     pub attenuation: Color,
     pub pdf: Option<Arc<dyn PDF>>,
+    pub dispersive: bool,
 }
 
 impl ScatterRecord {
     pub fn new(ray: Ray, attenuation: Color, pdf: Option<Arc<dyn PDF>>) -> ScatterRecord {
-        ScatterRecord { ray, attenuation, pdf }
+        ScatterRecord {
+            ray,
+            attenuation,
+            pdf,
+            dispersive: false,
+        }
     }
 }
 
 pub struct Lambertian {
     pub albedo: Arc<dyn Texture>,
+    id: u32,
 }
 
 impl Lambertian {
@@ -42,11 +78,17 @@ impl Lambertian {
         Lambertian::new_textured(Arc::new(SolidColor::new(c)))
     }
     pub fn new_textured(t: Arc<dyn Texture>) -> Lambertian {
-        Lambertian { albedo: t.clone() }
+        Lambertian {
+            albedo: t.clone(),
+            id: new_material_id(),
+        }
     }
 }
 
 impl Material for Lambertian {
+    fn id(&self) -> u32 {
+        self.id
+    }
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let uvw = ONB::build_from_w(&rec.normal);
         let direction = uvw.local_vec(&random_cosine_direction());
@@ -69,15 +111,23 @@ impl Material for Lambertian {
 pub struct Metal {
     pub albedo: Color,
     pub fuzz: f64,
+    id: u32,
 }
 
 impl Metal {
     pub fn new(c: Color, f: f64) -> Metal {
-        Metal { albedo: c, fuzz: f }
+        Metal {
+            albedo: c,
+            fuzz: f,
+            id: new_material_id(),
+        }
     }
 }
 
 impl Material for Metal {
+    fn id(&self) -> u32 {
+        self.id
+    }
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let reflected = r_in.dir.unit_vector().reflect(rec.normal);
         let scattered = Ray::new(rec.p, reflected + self.fuzz * Vec3::random_in_unit_sphere(), r_in.time);
@@ -87,15 +137,45 @@ impl Material for Metal {
             return None;
         }
     }
+    fn event(&self, _rec: &HitRecord, _srec: Option<&ScatterRecord>) -> Event {
+        Event::Specular
+    }
+}
+
+pub enum Ior {
+    Constant(f64),
+    // n = a + b / lambda^2, lambda in micrometres
+    Cauchy(f64, f64),
+    // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i), lambda in micrometres
+    Sellmeier([f64; 3], [f64; 3]),
+}
+
+impl Ior {
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.0).powi(2);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy(a, b) => a + b / l2,
+            Ior::Sellmeier(b, c) => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+        }
+    }
 }
 
 pub struct Dielectric {
-    ir: f64,
+    ior: Ior,
+    id: u32,
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Dielectric {
-        Dielectric { ir }
+        Dielectric::new_dispersive(Ior::Constant(ir))
+    }
+
+    pub fn new_dispersive(ior: Ior) -> Dielectric {
+        Dielectric {
+            ior,
+            id: new_material_id(),
+        }
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -105,9 +185,14 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
+    fn id(&self) -> u32 {
+        self.id
+    }
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let attenuation = Color::new(1., 1., 1.);
-        let refraction_ratio = if rec.front_face { 1.0 / self.ir } else { self.ir };
+        // without a wavelength (rgb mode) dispersive glass uses its d-line index
+        let ir = self.ior.at(r_in.wavelength.unwrap_or(587.6));
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
         let unit_direction = r_in.dir.unit_vector();
         let cos_theta = (-unit_direction.dot(rec.normal)).min(1.);
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
@@ -117,15 +202,24 @@ impl Material for Dielectric {
         } else {
             unit_direction.refract(rec.normal, refraction_ratio)
         };
-        return Some(ScatterRecord::new(Ray::new(rec.p, direction, r_in.time), attenuation, None));
+        let mut srec = ScatterRecord::new(Ray::new(rec.p, direction, r_in.time), attenuation, None);
+        srec.dispersive = !matches!(self.ior, Ior::Constant(_));
+        Some(srec)
+    }
+    fn event(&self, _rec: &HitRecord, _srec: Option<&ScatterRecord>) -> Event {
+        Event::Specular
     }
 }
 
 pub struct DiffuseLight {
     emit: Arc<dyn Texture>,
+    id: u32,
 }
 
 impl Material for DiffuseLight {
+    fn id(&self) -> u32 {
+        self.id
+    }
     fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
         None
     }
@@ -137,20 +231,170 @@ impl Material for DiffuseLight {
             Color::new(0.0, 0.0, 0.0)
         }
     }
+
+    fn average_emitted(&self) -> Color {
+        average_value(self.emit.as_ref())
+    }
 }
 
 impl DiffuseLight {
     pub fn new_textured(emit: Arc<dyn Texture>) -> DiffuseLight {
-        DiffuseLight { emit: emit }
+        DiffuseLight {
+            emit: emit,
+            id: new_material_id(),
+        }
     }
 
     pub fn new_color(emit_color: Color) -> DiffuseLight {
-        DiffuseLight {
-            emit: Arc::new(SolidColor::new(emit_color)),
+        DiffuseLight::new_textured(Arc::new(SolidColor::new(emit_color)))
+    }
+}
+
+pub enum AlphaMode {
+    Threshold(f64),
+    Stochastic,
+}
+
+pub struct AlphaMask {
+    base: Arc<dyn Material>,
+    opacity: Arc<dyn Texture>,
+    mode: AlphaMode,
+    id: u32,
+}
+
+impl AlphaMask {
+    pub fn new(base: Arc<dyn Material>, opacity: Arc<dyn Texture>, mode: AlphaMode) -> AlphaMask {
+        AlphaMask {
+            base,
+            opacity,
+            mode,
+            id: new_material_id(),
         }
     }
 }
 
+impl Material for AlphaMask {
+    fn id(&self) -> u32 {
+        self.id
+    }
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.base.scatter(r_in, rec)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.base.scattering_pdf(r_in, rec, scattered)
+    }
+    fn emitted(&self, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+        self.base.emitted(rec, u, v, p)
+    }
+    fn average_emitted(&self) -> Color {
+        self.base.average_emitted()
+    }
+    fn alpha_test(&self, rec: &HitRecord) -> bool {
+        let alpha = self.opacity.value(rec.u, rec.v, rec.p).x();
+        match self.mode {
+            AlphaMode::Threshold(cutoff) => alpha >= cutoff,
+            // hashed on the hit point so that repeated tests of the same hit
+            // (nested lists, BVH levels) agree with each other
+            AlphaMode::Stochastic => alpha > hash_f64(rec.p, 0),
+        }
+    }
+    fn event(&self, rec: &HitRecord, srec: Option<&ScatterRecord>) -> Event {
+        self.base.event(rec, srec)
+    }
+}
+
+pub struct MixMaterial {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    weight: Arc<dyn Texture>,
+    id: u32,
+}
+
+impl MixMaterial {
+    // weight 0 is all `a`, weight 1 is all `b`
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: Arc<dyn Texture>) -> MixMaterial {
+        MixMaterial {
+            a,
+            b,
+            weight,
+            id: new_material_id(),
+        }
+    }
+
+    fn weight_at(&self, rec: &HitRecord) -> f64 {
+        self.weight.value(rec.u, rec.v, rec.p).x().clamp(0.0, 1.0)
+    }
+}
+
+impl Material for MixMaterial {
+    fn id(&self) -> u32 {
+        self.id
+    }
+    // Picks one lobe per sample, with the probability of its weight. The
+    // scattering pdf is the blend of both, which is exact when the lobes
+    // share a pdf (as Lambertian ones do).
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let w = self.weight_at(rec);
+        let (lobe, other, p) = if random_f64() < w {
+            (&self.b, &self.a, w)
+        } else {
+            (&self.a, &self.b, 1.0 - w)
+        };
+        let mut srec = lobe.scatter(r_in, rec)?;
+        // a lobe that never scatters this way (a specular one, a light or an
+        // absorber) adds nothing to the blended pdf, so this lobe has to
+        // carry all of its own weight
+        if srec.pdf.is_some() && other.scattering_pdf(r_in, rec, &srec.ray) == 0.0 {
+            srec.attenuation /= p;
+        }
+        Some(srec)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let w = self.weight_at(rec);
+        (1.0 - w) * self.a.scattering_pdf(r_in, rec, scattered) + w * self.b.scattering_pdf(r_in, rec, scattered)
+    }
+    fn emitted(&self, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+        let w = self.weight.value(u, v, p).x();
+        (1.0 - w) * self.a.emitted(rec, u, v, p) + w * self.b.emitted(rec, u, v, p)
+    }
+    // the surface is where the heavier lobe says it is, the same for every
+    // test of a hit
+    fn alpha_test(&self, rec: &HitRecord) -> bool {
+        if self.weight_at(rec) < 0.5 {
+            self.a.alpha_test(rec)
+        } else {
+            self.b.alpha_test(rec)
+        }
+    }
+    fn average_emitted(&self) -> Color {
+        let w = average_value(self.weight.as_ref()).x();
+        (1.0 - w) * self.a.average_emitted() + w * self.b.average_emitted()
+    }
+    fn event(&self, rec: &HitRecord, srec: Option<&ScatterRecord>) -> Event {
+        let specular = |m: &Arc<dyn Material>| m.event(rec, None) == Event::Specular;
+        match srec {
+            // only a specular lobe scatters without a pdf
+            Some(s) if s.pdf.is_none() => Event::Specular,
+            Some(_) => Event::Diffuse,
+            None if specular(&self.a) && specular(&self.b) => Event::Specular,
+            None => Event::Diffuse,
+        }
+    }
+}
+
+// Mean of a texture over a grid of uv coordinates at the origin.
+fn average_value(t: &dyn Texture) -> Color {
+    const N: usize = 8;
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    for j in 0..N {
+        for i in 0..N {
+            let (u, v) = ((i as f64 + 0.5) / N as f64, (j as f64 + 0.5) / N as f64);
+            sum += t.value(u, v, Point3::new(0.0, 0.0, 0.0));
+        }
+    }
+    sum / (N * N) as f64
+}
+
 // pub struct Isotropic {
 //     albedo: Arc<dyn Texture>,
 // }
@@ -175,3 +419,54 @@ impl DiffuseLight {
 //         ))
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // bright over the left quarter of the uv square, black at its centre
+    struct Strip;
+
+    impl Texture for Strip {
+        fn value(&self, u: f64, _v: f64, _p: Point3) -> Color {
+            if u < 0.25 {
+                Color::new(2.0, 2.0, 2.0)
+            } else {
+                Color::new(0.0, 0.0, 0.0)
+            }
+        }
+    }
+
+    #[test]
+    fn textured_light_power_is_its_average() {
+        let light = DiffuseLight::new_textured(Arc::new(Strip));
+        assert!((light.average_emitted().x() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn half_a_light_reflects_half_the_albedo() {
+        seed_random(1);
+        let diffuse = Arc::new(Lambertian::new(Color::new(0.6, 0.6, 0.6)));
+        let light = Arc::new(DiffuseLight::new_color(Color::new(4.0, 4.0, 4.0)));
+        let half = Arc::new(SolidColor::new(Color::new(0.5, 0.5, 0.5)));
+        let mix = Arc::new(MixMaterial::new(diffuse, light, half));
+        let mut rec = HitRecord::new(Point3::new(0.0, 0.0, 0.0), 1.0, 0.5, 0.5, mix.clone());
+        rec.normal = Vec3::new(0.0, 1.0, 0.0);
+        rec.front_face = true;
+        let r_in = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+
+        // the weight the integrator gives light arriving from a direction
+        // drawn from the scatter's pdf
+        const N: usize = 20000;
+        let mut sum = 0.0;
+        for _ in 0..N {
+            if let Some(srec) = mix.scatter(&r_in, &rec) {
+                let pdf = srec.pdf.unwrap();
+                let scattered = Ray::new(rec.p, pdf.generate(), 0.0);
+                sum += srec.attenuation.x() * mix.scattering_pdf(&r_in, &rec, &scattered) / pdf.value(&scattered.dir);
+            }
+        }
+        let albedo = sum / N as f64;
+        assert!((albedo - 0.3).abs() < 0.01, "albedo {}", albedo);
+    }
+}
@@ -5,7 +5,7 @@ use crate::utils::random_f64;
 use crate::vec3::Vec3;
 use std::f64::consts::PI;
 use std::sync::Arc;
-pub trait PDF {
+pub trait PDF: Send + Sync {
     fn value(&self, direction: &Vec3) -> f64;
     fn generate(&self) -> Vec3;
 }
@@ -46,21 +46,22 @@ impl PDF for CosinePDF {
 
 pub struct HittablePDF {
     o: Point3,
+    time: f64,
     h: Arc<dyn Hittable>,
 }
 
 impl HittablePDF {
-    pub fn new(h: Arc<dyn Hittable>, o: Point3) -> HittablePDF {
-        HittablePDF { o, h }
+    pub fn new(h: Arc<dyn Hittable>, o: Point3, time: f64) -> HittablePDF {
+        HittablePDF { o, time, h }
     }
 }
 
 impl PDF for HittablePDF {
     fn value(&self, direction: &Vec3) -> f64 {
-        self.h.pdf_value(&self.o, direction)
+        self.h.pdf_value(&self.o, direction, self.time)
     }
     fn generate(&self) -> Vec3 {
-        self.h.random(&self.o)
+        self.h.random(&self.o, self.time)
     }
 }
 
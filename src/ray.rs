@@ -6,6 +6,7 @@ pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
     pub time: f64,
+    pub wavelength: Option<f64>,
 }
 
 impl Ray {
@@ -14,6 +15,7 @@ impl Ray {
             orig: orig,
             dir: dir,
             time: time,
+            wavelength: None,
         }
     }
 
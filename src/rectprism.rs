@@ -1,15 +1,17 @@
 use crate::aabb::Aabb;
 use crate::aarect::{XYRect, XZRect, YZRect};
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{new_object_id, HitRecord, Hittable};
 use crate::hittable_list::HittableList;
 use crate::material::Material;
 use crate::ray::{Point3, Ray};
+use crate::vec3::Vec3;
 use std::sync::Arc;
 
 pub struct RectPrism {
     min: Point3,
     max: Point3,
     sides: HittableList,
+    id: u32,
 }
 
 impl RectPrism {
@@ -18,6 +20,7 @@ impl RectPrism {
             min: p0,
             max: p1,
             sides: HittableList::new(),
+            id: new_object_id(),
         };
         r.sides
             .add(Arc::new(XYRect::new(p0.x(), p1.x(), p0.y(), p1.y(), p1.z(), mat.clone())));
@@ -36,10 +39,22 @@ impl RectPrism {
 }
 
 impl Hittable for RectPrism {
+    // one object, whichever side is hit
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.sides.hit(r, t_min, t_max)
+        let mut rec = self.sides.hit(r, t_min, t_max)?;
+        rec.object_id = self.id;
+        Some(rec)
     }
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(Aabb::new(self.min, self.max))
     }
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.sides.pdf_value(o, v, time)
+    }
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.sides.random(o, time)
+    }
+    fn power(&self) -> f64 {
+        self.sides.power()
+    }
 }
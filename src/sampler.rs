@@ -0,0 +1,353 @@
+use rand::prelude::*;
+
+// Per-pixel, per-dimension sample values. The renderer calls `start_pixel`
+// and `start_sample`; every `get_1d` after that consumes the next dimension.
+pub trait Sampler {
+    fn start_pixel(&mut self, x: u32, y: u32);
+    fn start_sample(&mut self, index: usize);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SamplerType {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+// Every sampler derives its values from (seed, pixel, sample index) alone,
+// so a render is reproducible no matter which thread handles which pixel.
+pub fn new_sampler(kind: SamplerType, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+    match kind {
+        SamplerType::Independent => Box::new(IndependentSampler::new(seed)),
+        SamplerType::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+        SamplerType::Halton => Box::new(HaltonSampler::new(seed)),
+        SamplerType::Sobol => Box::new(SobolSampler::new(seed)),
+    }
+}
+
+pub fn hash_u32(values: &[u32]) -> u32 {
+    let mut h: u32 = 0x9e37_79b9;
+    for &v in values {
+        h ^= v;
+        h = h.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 13;
+        h = h.wrapping_mul(0xc2b2_ae35);
+        h ^= h >> 16;
+    }
+    h
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4_294_967_296.0
+}
+
+#[derive(Default)]
+struct SampleState {
+    seed: [u32; 2],
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> SampleState {
+        SampleState {
+            seed: [seed as u32, (seed >> 32) as u32],
+            ..SampleState::default()
+        }
+    }
+
+    fn next_dimension(&mut self) -> u32 {
+        self.dimension += 1;
+        self.dimension - 1
+    }
+
+    fn seed(&self, dimension: u32) -> u32 {
+        hash_u32(&[self.seed[0], self.seed[1], self.x, self.y, dimension])
+    }
+
+    // for dimensions a sampler has no good points for
+    fn fallback(&self, dimension: u32) -> f64 {
+        to_unit(hash_u32(&[
+            self.seed[0],
+            self.seed[1],
+            self.x,
+            self.y,
+            self.index,
+            dimension,
+            0x5bd1_e995,
+        ]))
+    }
+}
+
+// A fresh generator per sample, seeded from the pixel and sample index.
+pub struct IndependentSampler {
+    rng: StdRng,
+    state: SampleState,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            rng: StdRng::seed_from_u64(seed),
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel(&mut self, x: u32, y: u32) {
+        self.state.x = x;
+        self.state.y = y;
+    }
+    fn start_sample(&mut self, index: usize) {
+        let s = &self.state;
+        let hi = hash_u32(&[s.seed[0], s.seed[1], s.x, s.y, index as u32]);
+        let lo = hash_u32(&[hi, index as u32]);
+        self.rng = StdRng::seed_from_u64((hi as u64) << 32 | lo as u64);
+    }
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+}
+
+// Jittered strata in every dimension, decorrelated between dimensions by
+// Kensler's hashed permutation (a Latin hypercube per pixel).
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> StratifiedSampler {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel as u32,
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel(&mut self, x: u32, y: u32) {
+        self.state.x = x;
+        self.state.y = y;
+    }
+    fn start_sample(&mut self, index: usize) {
+        self.state.index = index as u32;
+        self.state.dimension = 0;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let d = self.state.next_dimension();
+        if self.state.index >= self.samples_per_pixel {
+            return self.state.fallback(d);
+        }
+        let seed = self.state.seed(d);
+        let stratum = permute(self.state.index, self.samples_per_pixel, seed);
+        let jitter = to_unit(hash_u32(&[self.state.index, seed]));
+        (stratum as f64 + jitter) / self.samples_per_pixel as f64
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+fn radical_inverse(base: u32, mut i: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut r = 0.0;
+    while i > 0 {
+        r += (i % base) as f64 * inv;
+        i /= base;
+        inv *= inv_base;
+    }
+    r
+}
+
+// Halton points shared by all pixels, toroidally shifted per pixel.
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel(&mut self, x: u32, y: u32) {
+        self.state.x = x;
+        self.state.y = y;
+    }
+    fn start_sample(&mut self, index: usize) {
+        self.state.index = index as u32;
+        self.state.dimension = 0;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let d = self.state.next_dimension();
+        if d as usize >= PRIMES.len() {
+            return self.state.fallback(d);
+        }
+        let shift = to_unit(self.state.seed(d));
+        (radical_inverse(PRIMES[d as usize], self.state.index) + shift).fract()
+    }
+}
+
+fn sobol_2d(mut i: u32) -> (u32, u32) {
+    let mut x = 0;
+    let mut y = 0;
+    let mut v = 1 << 31;
+    let mut bit = 1 << 31;
+    while i > 0 {
+        if i & 1 == 1 {
+            x ^= bit;
+            y ^= v;
+        }
+        i >>= 1;
+        bit >>= 1;
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Owen-scrambled 2D Sobol points padded over pairs of dimensions, with the
+// sample order shuffled per pair (Burley 2020).
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel(&mut self, x: u32, y: u32) {
+        self.state.x = x;
+        self.state.y = y;
+    }
+    fn start_sample(&mut self, index: usize) {
+        self.state.index = index as u32;
+        self.state.dimension = 0;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let d = self.state.next_dimension();
+        let pair_seed = self.state.seed(d / 2);
+        let index = nested_uniform_scramble(self.state.index, pair_seed);
+        let (x, y) = sobol_2d(index);
+        let v = if d & 1 == 0 { x } else { y };
+        to_unit(nested_uniform_scramble(v, hash_u32(&[pair_seed, d & 1])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel_samples(kind: SamplerType, n: usize, dimensions: usize) -> Vec<Vec<f64>> {
+        let mut sampler = new_sampler(kind, n, 7);
+        sampler.start_pixel(3, 5);
+        (0..n)
+            .map(|i| {
+                sampler.start_sample(i);
+                (0..dimensions).map(|_| sampler.get_1d()).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_sample_per_stratum_in_every_dimension() {
+        let n = 16;
+        for kind in [SamplerType::Stratified, SamplerType::Sobol] {
+            let samples = pixel_samples(kind, n, 6);
+            for d in 0..6 {
+                let mut strata: Vec<usize> = samples.iter().map(|s| (s[d] * n as f64) as usize).collect();
+                strata.sort();
+                assert_eq!(strata, (0..n).collect::<Vec<_>>(), "dimension {}", d);
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_pairs_are_stratified_in_two_dimensions() {
+        // 16 points of a (0, 2)-sequence put one point in each 4x4 cell
+        let samples = pixel_samples(SamplerType::Sobol, 16, 2);
+        let mut cells: Vec<usize> = samples.iter().map(|s| (s[0] * 4.0) as usize * 4 + (s[1] * 4.0) as usize).collect();
+        cells.sort();
+        assert_eq!(cells, (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn samples_depend_only_on_pixel_and_index() {
+        for kind in [
+            SamplerType::Independent,
+            SamplerType::Stratified,
+            SamplerType::Halton,
+            SamplerType::Sobol,
+        ] {
+            let mut sampler = new_sampler(kind, 8, 7);
+            sampler.start_pixel(3, 5);
+            sampler.start_sample(6);
+            let direct: Vec<f64> = (0..4).map(|_| sampler.get_1d()).collect();
+            assert_eq!(pixel_samples(kind, 8, 4)[6], direct);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::color::*;
+use crate::environment::Environment;
+use crate::layered::RGB_WAVELENGTHS;
+use crate::onb::ONB;
+use crate::utils::*;
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+// angular radius of the sun disk, in degrees
+const SUN_ANGULAR_RADIUS: f64 = 0.267;
+// sun radiance relative to a zenith sky luminance of 1
+const SUN_RADIANCE: f64 = 2.0e5;
+
+// Unit vector towards a sun at the given elevation and azimuth (degrees).
+// Azimuth is measured clockwise from north, with north along -z and east along +x.
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    let e = degrees_to_radians(elevation);
+    let a = degrees_to_radians(azimuth);
+    Vec3::new(e.cos() * a.sin(), e.sin(), -e.cos() * a.cos())
+}
+
+fn day_of_year(year: i32, month: u32, day: u32) -> u32 {
+    const CUMULATIVE_DAYS: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month = month.clamp(1, 12);
+    CUMULATIVE_DAYS[month as usize - 1] + day + if leap && month > 2 { 1 } else { 0 }
+}
+
+// Solar (elevation, azimuth) in degrees using the NOAA approximation.
+// Latitude is positive north, longitude positive east, time is UTC hours.
+pub fn sun_position(latitude: f64, longitude: f64, year: i32, month: u32, day: u32, utc_hours: f64) -> (f64, f64) {
+    let gamma = 2.0 * PI / 365.0 * (day_of_year(year, month, day) as f64 - 1.0 + (utc_hours - 12.0) / 24.0);
+    let eqtime = 229.18
+        * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin() - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+    let decl = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2.0 * gamma).cos() + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+
+    let true_solar_time = utc_hours * 60.0 + eqtime + 4.0 * longitude;
+    let hour_angle = degrees_to_radians(true_solar_time / 4.0 - 180.0);
+    let lat = degrees_to_radians(latitude);
+
+    let cos_zenith = lat.sin() * decl.sin() + lat.cos() * decl.cos() * hour_angle.cos();
+    let elevation = 90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees();
+    let azimuth = hour_angle
+        .sin()
+        .atan2(hour_angle.cos() * lat.sin() - decl.tan() * lat.cos())
+        .to_degrees()
+        + 180.0;
+    (elevation, azimuth)
+}
+
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn eval(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta).exp()) * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+// Preetham et al. analytic daylight with a sampled sun disk.
+pub struct PreethamSky {
+    sun_dir: Vec3,
+    perez_y: Perez,
+    perez_x: Perez,
+    perez_yy: Perez,
+    zenith: [f64; 3],
+    sun_radiance: Color,
+    cos_sun_max: f64,
+    intensity: f64,
+    average: Color,
+}
+
+impl PreethamSky {
+    pub fn new(sun_dir: Vec3, turbidity: f64, intensity: f64) -> PreethamSky {
+        let t = turbidity;
+        let sun_dir = sun_dir.unit_vector();
+        let theta_s = sun_dir.y().clamp(0.0, 1.0).acos();
+
+        let th = theta_s;
+        let zenith_x = t * t * (0.00166 * th.powi(3) - 0.00375 * th.powi(2) + 0.00209 * th)
+            + t * (-0.02903 * th.powi(3) + 0.06377 * th.powi(2) - 0.03202 * th + 0.00394)
+            + (0.11693 * th.powi(3) - 0.21196 * th.powi(2) + 0.06052 * th + 0.25886);
+        let zenith_yy = t * t * (0.00275 * th.powi(3) - 0.00610 * th.powi(2) + 0.00317 * th)
+            + t * (-0.04214 * th.powi(3) + 0.08970 * th.powi(2) - 0.04153 * th + 0.00516)
+            + (0.15346 * th.powi(3) - 0.26756 * th.powi(2) + 0.06670 * th + 0.26688);
+
+        let perez_y = Perez {
+            a: 0.1787 * t - 1.4630,
+            b: -0.3554 * t + 0.4275,
+            c: -0.0227 * t + 5.3251,
+            d: 0.1206 * t - 2.5771,
+            e: -0.0670 * t + 0.3703,
+        };
+        let perez_x = Perez {
+            a: -0.0193 * t - 0.2592,
+            b: -0.0665 * t + 0.0008,
+            c: -0.0004 * t + 0.2125,
+            d: -0.0641 * t - 0.8989,
+            e: -0.0033 * t + 0.0452,
+        };
+        let perez_yy = Perez {
+            a: -0.0167 * t - 0.2608,
+            b: -0.0950 * t + 0.0092,
+            c: -0.0079 * t + 0.2102,
+            d: -0.0441 * t - 1.6537,
+            e: -0.0109 * t + 0.0529,
+        };
+
+        // normalise so that the zenith has unit luminance
+        let zenith = [
+            1.0 / perez_y.eval(1.0, theta_s),
+            zenith_x / perez_x.eval(1.0, theta_s),
+            zenith_yy / perez_yy.eval(1.0, theta_s),
+        ];
+
+        let mut sky = PreethamSky {
+            sun_dir,
+            perez_y,
+            perez_x,
+            perez_yy,
+            zenith,
+            sun_radiance: SUN_RADIANCE * sun_transmittance(theta_s, t),
+            cos_sun_max: degrees_to_radians(SUN_ANGULAR_RADIUS).cos(),
+            intensity,
+            average: Color::new(0.0, 0.0, 0.0),
+        };
+        sky.average = sky.average_radiance();
+        sky
+    }
+
+    pub fn new_from_angles(elevation: f64, azimuth: f64, turbidity: f64, intensity: f64) -> PreethamSky {
+        PreethamSky::new(sun_direction(elevation, azimuth), turbidity, intensity)
+    }
+
+    fn sky(&self, dir: &Vec3) -> Color {
+        let cos_theta = dir.y().max(0.001);
+        let gamma = dir.dot(self.sun_dir).clamp(-1.0, 1.0).acos();
+        let y = self.zenith[0] * self.perez_y.eval(cos_theta, gamma);
+        let x = self.zenith[1] * self.perez_x.eval(cos_theta, gamma);
+        let yy = self.zenith[2] * self.perez_yy.eval(cos_theta, gamma);
+        let rgb = xyz_to_rgb(Vec3::new(x * y / yy, y, (1.0 - x - yy) * y / yy));
+        Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    // The sky over a coarse grid of directions, weighted by solid angle as
+    // EnvironmentMap does, plus the sun disk, which the grid would miss.
+    fn average_radiance(&self) -> Color {
+        const WIDTH: usize = 64;
+        const HEIGHT: usize = 32;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        let mut total_sin = 0.0;
+        for j in 0..HEIGHT {
+            let (sin, cos) = (PI * (j as f64 + 0.5) / HEIGHT as f64).sin_cos();
+            for i in 0..WIDTH {
+                let (sin_phi, cos_phi) = (2.0 * PI * (i as f64 + 0.5) / WIDTH as f64).sin_cos();
+                sum += sin * self.sky(&Vec3::new(sin * cos_phi, cos, sin * sin_phi));
+                total_sin += sin;
+            }
+        }
+        let mut average = sum / total_sin;
+        if self.sun_dir.y() > 0.0 {
+            // the disk's share of the whole sphere of directions
+            average += 0.5 * (1.0 - self.cos_sun_max) * self.sun_radiance;
+        }
+        self.intensity * average
+    }
+
+    fn sun_probability(&self) -> f64 {
+        if self.sun_dir.y() > 0.0 {
+            0.5
+        } else {
+            0.0
+        }
+    }
+}
+
+// Rayleigh and aerosol extinction along the air mass towards the sun
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> Color {
+    let beta = 0.04608 * turbidity - 0.04586;
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let mut c = Color::new(0.0, 0.0, 0.0);
+    for i in 0..3 {
+        let lambda = RGB_WAVELENGTHS[i] / 1000.0;
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+        c[i] = rayleigh * aerosol;
+    }
+    c
+}
+
+impl Environment for PreethamSky {
+    fn value(&self, dir: &Vec3) -> Color {
+        let d = dir.unit_vector();
+        let mut c = self.sky(&d);
+        if d.y() > 0.0 && d.dot(self.sun_dir) >= self.cos_sun_max {
+            c += self.sun_radiance;
+        }
+        self.intensity * c
+    }
+
+    fn pdf_value(&self, dir: &Vec3) -> f64 {
+        let d = dir.unit_vector();
+        let p_sun = self.sun_probability();
+        let mut pdf = 0.0;
+        if d.dot(self.sun_dir) >= self.cos_sun_max {
+            pdf += p_sun / (2.0 * PI * (1.0 - self.cos_sun_max));
+        }
+        if d.y() > 0.0 {
+            pdf += (1.0 - p_sun) / (2.0 * PI);
+        }
+        pdf
+    }
+
+    fn random(&self) -> Vec3 {
+        if random_f64() < self.sun_probability() {
+            let sin_sun_max = (1.0 - self.cos_sun_max * self.cos_sun_max).sqrt();
+            ONB::build_from_w(&self.sun_dir).local_vec(&Vec3::random_to_sphere(sin_sun_max, 1.0))
+        } else {
+            let v = Vec3::random_unit_vector();
+            if v.y() < 0.0 {
+                -v
+            } else {
+                v
+            }
+        }
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+
+    fn average(&self) -> Color {
+        self.average
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::EnvironmentLight;
+    use crate::hittable::Hittable;
+    use std::sync::Arc;
+
+    #[test]
+    fn average_matches_uniform_sampling() {
+        // with the sun set there is no disk for uniform samples to miss
+        seed_random(2);
+        let sky = PreethamSky::new_from_angles(-5.0, 120.0, 3.0, 1.0);
+        const N: usize = 100000;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..N {
+            sum += sky.value(&Vec3::random_unit_vector());
+        }
+        let (expected, average) = (luminance(sum / N as f64), luminance(sky.average()));
+        assert!((average - expected).abs() < 0.02 * expected, "{} vs {}", average, expected);
+    }
+
+    #[test]
+    fn a_sunlit_sky_has_power_including_the_disk() {
+        seed_random(3);
+        let day = Arc::new(PreethamSky::new_from_angles(40.0, 120.0, 3.0, 1.0));
+        let disk = 0.5 * (1.0 - day.cos_sun_max) * luminance(day.sun_radiance);
+        let mut sky = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..100000 {
+            let v = Vec3::random_unit_vector();
+            sky += day.sky(&v) / 100000.0;
+        }
+        assert!((luminance(day.average()) - luminance(sky) - disk).abs() < 0.02 * luminance(sky));
+        assert!(EnvironmentLight::new(day, 1.0).power() > 0.0);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::color::*;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::sync::OnceLock;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+// integral of the CIE y bar function over the visible range
+const CIE_Y_INTEGRAL: f64 = 106.856895;
+
+fn gaussian(x: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 colour matching functions, multi-lobe fit of Wyman et al. 2013
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    Vec3::new(
+        1.056 * gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2),
+        0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1),
+        1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+// The hero wavelength plus two more spread evenly over the visible range.
+// The three are carried in the channels of a `Color` along the path.
+pub fn wavelengths(hero: f64) -> [f64; 3] {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let mut l = [hero; 3];
+    for (i, w) in l.iter_mut().enumerate() {
+        *w = LAMBDA_MIN + (hero - LAMBDA_MIN + i as f64 * range / 3.0) % range;
+    }
+    l
+}
+
+fn basis(lambda: f64) -> Vec3 {
+    Vec3::new(
+        gaussian(lambda, 610.0, 30.0, 40.0),
+        gaussian(lambda, 545.0, 35.0, 35.0),
+        gaussian(lambda, 460.0, 40.0, 30.0),
+    )
+}
+
+// Maps an rgb triple to weights of the three basis spectra, chosen so that
+// the basis spectra project back onto exactly that rgb triple.
+fn basis_weights() -> &'static [Vec3; 3] {
+    static WEIGHTS: OnceLock<[Vec3; 3]> = OnceLock::new();
+    WEIGHTS.get_or_init(|| {
+        let mut m = [Vec3::new(0.0, 0.0, 0.0); 3];
+        let mut lambda = LAMBDA_MIN;
+        while lambda < LAMBDA_MAX {
+            let b = basis(lambda);
+            let rgb = xyz_to_rgb(cie_xyz(lambda)) / CIE_Y_INTEGRAL;
+            for (row, r) in m.iter_mut().zip(rgb.a) {
+                *row += r * b;
+            }
+            lambda += 1.0;
+        }
+        invert(m)
+    })
+}
+
+fn invert(m: [Vec3; 3]) -> [Vec3; 3] {
+    let c0 = m[1].cross(m[2]);
+    let c1 = m[2].cross(m[0]);
+    let c2 = m[0].cross(m[1]);
+    let inv_det = 1.0 / m[0].dot(c0);
+    [
+        inv_det * Vec3::new(c0.x(), c1.x(), c2.x()),
+        inv_det * Vec3::new(c0.y(), c1.y(), c2.y()),
+        inv_det * Vec3::new(c0.z(), c1.z(), c2.z()),
+    ]
+}
+
+pub fn rgb_to_spectrum(c: Color, lambda: f64) -> f64 {
+    let w = basis_weights();
+    let weights = Vec3::new(w[0].dot(c), w[1].dot(c), w[2].dot(c));
+    weights.dot(basis(lambda)).max(0.0)
+}
+
+// Upsamples an rgb value to the wavelengths carried by the ray, if any.
+pub fn to_spectral(c: Color, r: &Ray) -> Color {
+    if let Some(hero) = r.wavelength {
+        let l = wavelengths(hero);
+        Color::new(rgb_to_spectrum(c, l[0]), rgb_to_spectrum(c, l[1]), rgb_to_spectrum(c, l[2]))
+    } else {
+        c
+    }
+}
+
+// Converts radiance at the hero wavelengths to linear rgb, for a hero
+// wavelength drawn uniformly from the visible range.
+pub fn spectral_to_rgb(radiance: Color, hero: f64) -> Color {
+    let l = wavelengths(hero);
+    let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+    for i in 0..3 {
+        xyz += radiance[i] * cie_xyz(l[i]);
+    }
+    xyz_to_rgb(xyz * (LAMBDA_MAX - LAMBDA_MIN) / (3.0 * CIE_Y_INTEGRAL))
+}
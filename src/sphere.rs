@@ -1,4 +1,5 @@
 use crate::aabb::*;
+use crate::color::luminance;
 use crate::hittable::*;
 use crate::material::*;
 use crate::prelude::Vec3;
@@ -12,6 +13,7 @@ pub struct Sphere {
     center: Point3,
     radius: f64,
     material: Arc<dyn Material>,
+    id: u32,
 }
 
 impl Sphere {
@@ -20,6 +22,7 @@ impl Sphere {
             center: c,
             radius: r,
             material: m,
+            id: new_object_id(),
         }
     }
 }
@@ -47,6 +50,7 @@ impl Hittable for Sphere {
         let outward_normal = (r.at(root) - self.center) / self.radius;
         let (u, v) = get_uv(outward_normal);
         let mut rec = HitRecord::new(r.at(root), root, u, v, self.material.clone());
+        rec.object_id = self.id;
         rec.set_face_normal(r, &outward_normal);
         Some(rec)
     }
@@ -58,21 +62,20 @@ impl Hittable for Sphere {
         ))
     }
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
-        if let Some(_rec) = self.hit(&Ray::new(*o, *v, 0.0), 0.001, f64::INFINITY) {
-            let cos_theta_max = (1.0 - self.radius * self.radius / (self.center - *o).length_squared()).sqrt();
-            let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
-            1.0 / solid_angle
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        if let Some(_rec) = self.hit(&Ray::new(*o, *v, time), 0.001, f64::INFINITY) {
+            sphere_pdf_value(self.center, self.radius, o)
         } else {
             0.0
         }
     }
 
-    fn random(&self, o: &Vec3) -> Vec3 {
-        let dir = self.center - *o;
-        let distance_squared = dir.length_squared();
-        let uvw = ONB::build_from_w(&dir);
-        uvw.local_vec(&Vec3::random_to_sphere(self.radius, distance_squared))
+    fn random(&self, o: &Vec3, _time: f64) -> Vec3 {
+        sphere_random(self.center, self.radius, o)
+    }
+
+    fn power(&self) -> f64 {
+        sphere_power(&self.material, self.radius)
     }
 }
 
@@ -83,6 +86,7 @@ pub struct MovingSphere {
     time1: f64,
     radius: f64,
     material: Arc<dyn Material>,
+    id: u32,
 }
 
 impl MovingSphere {
@@ -94,11 +98,12 @@ impl MovingSphere {
             time1: time1,
             radius: radius,
             material: material,
+            id: new_object_id(),
         }
     }
 
     fn center(&self, time: f64) -> Point3 {
-        self.center0 - ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
+        self.center0 + ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
     }
 }
 
@@ -126,6 +131,7 @@ impl Hittable for MovingSphere {
         let outward_normal = (r.at(root) - self.center(r.time)) / self.radius;
         let (u, v) = get_uv(outward_normal);
         let mut rec = HitRecord::new(r.at(root), root, u, v, self.material.clone());
+        rec.object_id = self.id;
         rec.set_face_normal(r, &outward_normal);
         Some(rec)
     }
@@ -141,6 +147,39 @@ impl Hittable for MovingSphere {
         );
         Some(Aabb::surrounding_box(box0, box1))
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        if let Some(_rec) = self.hit(&Ray::new(*o, *v, time), 0.001, f64::INFINITY) {
+            sphere_pdf_value(self.center(time), self.radius, o)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        sphere_random(self.center(time), self.radius, o)
+    }
+
+    fn power(&self) -> f64 {
+        sphere_power(&self.material, self.radius)
+    }
+}
+
+fn sphere_power(material: &Arc<dyn Material>, radius: f64) -> f64 {
+    4.0 * PI * PI * radius * radius * luminance(material.average_emitted())
+}
+
+fn sphere_pdf_value(center: Point3, radius: f64, o: &Point3) -> f64 {
+    let cos_theta_max = (1.0 - radius * radius / (center - *o).length_squared()).sqrt();
+    let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+    1.0 / solid_angle
+}
+
+fn sphere_random(center: Point3, radius: f64, o: &Point3) -> Vec3 {
+    let dir = center - *o;
+    let distance_squared = dir.length_squared();
+    let uvw = ONB::build_from_w(&dir);
+    uvw.local_vec(&Vec3::random_to_sphere(radius, distance_squared))
 }
 
 fn get_uv(p: Point3) -> (f64, f64) {
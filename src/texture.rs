@@ -5,10 +5,13 @@ use std::sync::Arc;
 
 extern crate image;
 use image::io::Reader as ImageReader;
-use image::RgbImage;
+use image::RgbaImage;
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+    fn alpha(&self, _u: f64, _v: f64, _p: Point3) -> f64 {
+        1.0
+    }
 }
 
 pub struct SolidColor {
@@ -87,23 +90,15 @@ impl NoiseTexture {
 }
 
 pub struct ImageTexture {
-    image: RgbImage,
+    image: RgbaImage,
     width: u32,
     height: u32,
 }
 
 impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
-        let uc = u.clamp(0., 1.);
-        let vc = 1. - v.clamp(0., 1.);
-
-        let mut i: u32 = (uc * (self.width as f64)).round() as u32;
-        let mut j: u32 = (vc * (self.height as f64)).round() as u32;
-        i = if i >= self.width { self.width - 1 } else { i };
-        j = if j >= self.height { self.height - 1 } else { j };
-
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
         let color_scale = 1. / 255.;
-        let p = self.image.get_pixel(i, j);
+        let p = self.texel(u, v);
 
         Color::new(
             color_scale * (p[0] as f64),
@@ -111,11 +106,15 @@ impl Texture for ImageTexture {
             color_scale * (p[2] as f64),
         )
     }
+
+    fn alpha(&self, u: f64, v: f64, _p: Point3) -> f64 {
+        self.texel(u, v)[3] as f64 / 255.
+    }
 }
 
 impl ImageTexture {
     pub fn new(p: &std::path::Path) -> ImageTexture {
-        let i = ImageReader::open(p).unwrap().decode().unwrap().to_rgb8();
+        let i = ImageReader::open(p).unwrap().decode().unwrap().to_rgba8();
         let (w, h) = i.dimensions();
         ImageTexture {
             image: i,
@@ -123,4 +122,33 @@ impl ImageTexture {
             height: h,
         }
     }
+
+    fn texel(&self, u: f64, v: f64) -> &image::Rgba<u8> {
+        let uc = u.clamp(0., 1.);
+        let vc = 1. - v.clamp(0., 1.);
+
+        let mut i: u32 = (uc * (self.width as f64)).round() as u32;
+        let mut j: u32 = (vc * (self.height as f64)).round() as u32;
+        i = if i >= self.width { self.width - 1 } else { i };
+        j = if j >= self.height { self.height - 1 } else { j };
+
+        self.image.get_pixel(i, j)
+    }
+}
+
+pub struct AlphaChannel {
+    texture: Arc<dyn Texture>,
+}
+
+impl AlphaChannel {
+    pub fn new(texture: Arc<dyn Texture>) -> AlphaChannel {
+        AlphaChannel { texture }
+    }
+}
+
+impl Texture for AlphaChannel {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let a = self.texture.alpha(u, v, p);
+        Color::new(a, a, a)
+    }
 }
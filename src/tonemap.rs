@@ -0,0 +1,111 @@
+use crate::color::*;
+
+#[derive(Clone, Copy)]
+pub enum ToneMapOperator {
+    Linear,
+    // luminance based, with the luminance that maps to white
+    Reinhard(f64),
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+    // Hable's Uncharted 2 curve
+    Hable,
+}
+
+// Turns linear film radiance into display values.
+pub struct ToneMapper {
+    operator: ToneMapOperator,
+    exposure: f64,
+}
+
+impl ToneMapper {
+    // exposure is in stops (EV)
+    pub fn new(operator: ToneMapOperator, exposure: f64) -> ToneMapper {
+        ToneMapper { operator, exposure }
+    }
+
+    pub fn map(&self, c: Color) -> Color {
+        let c = 2f64.powf(self.exposure) * c;
+        let c = Color::new(c.x().max(0.0), c.y().max(0.0), c.z().max(0.0));
+        match self.operator {
+            ToneMapOperator::Linear => c,
+            ToneMapOperator::Reinhard(white) => {
+                let l = luminance(c);
+                if l <= 0.0 {
+                    return c;
+                }
+                let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+                mapped / l * c
+            }
+            ToneMapOperator::Aces => {
+                let aces = |x: f64| {
+                    let x = 0.6 * x;
+                    x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)
+                };
+                Color::new(aces(c.x()), aces(c.y()), aces(c.z()))
+            }
+            ToneMapOperator::Hable => {
+                let white_scale = 1.0 / hable(11.2);
+                Color::new(
+                    hable(2.0 * c.x()) * white_scale,
+                    hable(2.0 * c.y()) * white_scale,
+                    hable(2.0 * c.z()) * white_scale,
+                )
+            }
+        }
+    }
+
+    pub fn to_rgb8(&self, c: Color) -> [u8; 3] {
+        let c = self.map(c);
+        let quantize = |x: f64| (255.0 * linear_to_srgb(x.clamp(0.0, 1.0)) + 0.5) as u8;
+        [quantize(c.x()), quantize(c.y()), quantize(c.z())]
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+pub fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(x: f64) -> Color {
+        Color::new(x, x, x)
+    }
+
+    #[test]
+    fn operators_are_monotonic_from_black() {
+        for operator in [
+            ToneMapOperator::Linear,
+            ToneMapOperator::Reinhard(4.0),
+            ToneMapOperator::Aces,
+            ToneMapOperator::Hable,
+        ] {
+            let tone = ToneMapper::new(operator, 0.0);
+            assert!(tone.map(grey(0.0)).x().abs() < 1e-9);
+            let mut last = 0.0;
+            for i in 1..100 {
+                let y = tone.map(grey(0.1 * i as f64)).x();
+                assert!(y > last);
+                last = y;
+            }
+        }
+    }
+
+    #[test]
+    fn white_points_map_to_one() {
+        assert!((ToneMapper::new(ToneMapOperator::Reinhard(4.0), 0.0).map(grey(4.0)).x() - 1.0).abs() < 1e-9);
+        assert!((ToneMapper::new(ToneMapOperator::Hable, 0.0).map(grey(5.6)).x() - 1.0).abs() < 1e-9);
+        // one stop of exposure doubles linear values
+        assert!((ToneMapper::new(ToneMapOperator::Linear, 1.0).map(grey(0.3)).x() - 0.6).abs() < 1e-9);
+    }
+}
@@ -1,19 +1,70 @@
+use crate::ray::Point3;
+use crate::sampler::Sampler;
 use rand::prelude::*;
+use std::cell::RefCell;
+
+thread_local! {
+    static SAMPLER: RefCell<Option<Box<dyn Sampler>>> = RefCell::new(None);
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+// Reseeds the generator random_f64 falls back to when no sampler is set,
+// which is what scene construction (BVH splits, Perlin tables) draws from.
+pub fn seed_random(seed: u64) {
+    RNG.with(|r| *r.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+// Installs the sampler that random_f64 draws from on this thread.
+pub fn set_sampler(sampler: Box<dyn Sampler>) {
+    SAMPLER.with(|s| *s.borrow_mut() = Some(sampler));
+}
+
+pub fn with_sampler<F: FnOnce(&mut dyn Sampler)>(f: F) {
+    SAMPLER.with(|s| {
+        if let Some(sampler) = s.borrow_mut().as_mut() {
+            f(sampler.as_mut())
+        }
+    });
+}
 
 pub fn random_f64() -> f64 {
-    let mut rng = thread_rng();
-    rng.gen()
+    SAMPLER.with(|s| match s.borrow_mut().as_mut() {
+        Some(sampler) => sampler.get_1d(),
+        None => RNG.with(|r| r.borrow_mut().gen()),
+    })
+}
+
+// Two dimensions the sampler treats as a pair (e.g. one 2D Sobol point).
+pub fn random_2d() -> (f64, f64) {
+    SAMPLER.with(|s| match s.borrow_mut().as_mut() {
+        Some(sampler) => sampler.get_2d(),
+        None => RNG.with(|r| {
+            let mut r = r.borrow_mut();
+            (r.gen(), r.gen())
+        }),
+    })
 }
 
 pub fn random_range(min: f64, max: f64) -> f64 {
-    let mut rng = thread_rng();
-    rng.gen_range(min..max)
+    min + (max - min) * random_f64()
 }
 
 pub fn random_int(min: usize, max: usize) -> usize {
     random_range(min as f64, (max + 1) as f64).floor() as usize
 }
 
+pub fn hash_f64(p: Point3, seed: u64) -> f64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for c in p.a {
+        h ^= c.to_bits();
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
 pub fn degrees_to_radians(deg: f64) -> f64 {
     deg * std::f64::consts::PI / 180.0
 }
@@ -25,13 +25,10 @@ impl Vec3 {
         }
     }
 
+    // a direction scaled by the cube root of a third dimension, rather than
+    // rejection, so every call uses exactly three sample dimensions
     pub fn random_in_unit_sphere() -> Vec3 {
-        loop {
-            let p = Vec3::random_range(-1.0, 1.0);
-            if p.length_squared() < 1. {
-                return p;
-            }
-        }
+        Vec3::random_unit_vector() * random_f64().cbrt()
     }
 
     pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
@@ -43,20 +40,28 @@ impl Vec3 {
         }
     }
 
+    // concentric mapping so that exactly two sample dimensions are used
     pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let p = Vec3 {
-                a: [random_range(-1., 1.), random_range(-1., 1.), 0.],
-            };
-            if p.length_squared() < 1. {
-                return p;
-            }
+        let (u, v) = random_2d();
+        let a = 2. * u - 1.;
+        let b = 2. * v - 1.;
+        if a == 0. && b == 0. {
+            return Vec3::new(0., 0., 0.);
         }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, PI / 4. * (b / a))
+        } else {
+            (b, PI / 2. - PI / 4. * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.)
     }
 
     pub fn random_unit_vector() -> Vec3 {
-        Self::random_in_unit_sphere().unit_vector()
-        // Self::random().unit_vector()
+        let (u, v) = random_2d();
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
     }
 
     pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {